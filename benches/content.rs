@@ -0,0 +1,78 @@
+//! Compares the compiled content matcher with checking the words one by one, the way the JSON datasource used to.
+//!
+//! Run with `cargo bench --bench content`.
+
+use chief::engine::content::{ContentMatcher, ContentRule, Normalization, PatternType};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+
+const CONTENT: &str = "Good morning everyone! Spent the weekend hiking up to the lake with the kids, the weather \
+    was perfect and we even saw a couple of deer near the trail. Back to work now, still catching up on the relay \
+    migration notes from last week. If anyone has tips for keeping a small strfry instance healthy on a cheap VPS \
+    I would love to hear them. Have a great day and see you all at the meetup on Thursday!";
+
+/// Phrases that look like a spam list: two or three pseudo-random lowercase words, none of them in `CONTENT`
+fn words(count: usize) -> Vec<String> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    (0..count)
+        .map(|_| {
+            let parts = 2 + next() % 2;
+            (0..parts)
+                .map(|_| word(&mut next))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect()
+}
+
+fn word(next: &mut impl FnMut() -> u64) -> String {
+    let len = 4 + next() % 6;
+    (0..len)
+        .map(|_| char::from(b'a' + (next() % 26) as u8))
+        .collect()
+}
+
+fn bench_content(c: &mut Criterion) {
+    let mut group = c.benchmark_group("content");
+    group.throughput(Throughput::Bytes(CONTENT.len() as u64));
+    for count in [100, 1_000, 10_000, 50_000] {
+        let words = words(count);
+
+        let lowercase_words: Vec<String> = words.iter().map(|w| w.to_lowercase()).collect();
+        group.bench_with_input(
+            BenchmarkId::new("contains", count),
+            CONTENT,
+            |b, content| {
+                b.iter(|| {
+                    let content = content.to_lowercase();
+                    lowercase_words
+                        .iter()
+                        .any(|word| black_box(&content).contains(word.as_str()))
+                })
+            },
+        );
+
+        let rules: Vec<ContentRule> = words
+            .into_iter()
+            .map(|word| ContentRule {
+                word,
+                pattern_type: PatternType::Substring,
+                normalize: Normalization::default(),
+            })
+            .collect();
+        let matcher = ContentMatcher::new(&rules, false).unwrap();
+        group.bench_with_input(BenchmarkId::new("matcher", count), CONTENT, |b, content| {
+            b.iter(|| matcher.is_match(black_box(content)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_content);
+criterion_main!(benches);
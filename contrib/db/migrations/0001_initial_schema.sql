@@ -0,0 +1,24 @@
+-- Tables for the public keys, kinds and words lists. IF NOT EXISTS makes this a no-op on databases
+-- that were set up by hand with contrib/db/0002_initialize_db.sql.
+
+CREATE TABLE IF NOT EXISTS public_keys
+(
+    id        SERIAL PRIMARY KEY,
+    publickey TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS kinds
+(
+    id   SERIAL PRIMARY KEY,
+    kind INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS words
+(
+    id   SERIAL PRIMARY KEY,
+    word TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_publickeys_publickey ON public_keys(publickey);
+CREATE INDEX IF NOT EXISTS idx_words_word ON words(word);
+CREATE INDEX IF NOT EXISTS idx_kinds_kind ON kinds(kind);
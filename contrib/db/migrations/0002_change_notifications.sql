@@ -0,0 +1,49 @@
+-- Publish every change to the filter lists on the chief_changes channel, so chief instances running with
+-- `cache = true` can update their in-memory copy without polling the database.
+
+CREATE OR REPLACE FUNCTION chief_notify_change() RETURNS trigger AS
+$$
+DECLARE
+    payload TEXT;
+BEGIN
+    IF TG_OP = 'TRUNCATE' THEN
+        payload := json_build_object('table', TG_TABLE_NAME, 'op', TG_OP)::TEXT;
+    ELSE
+        payload := json_build_object(
+                'table', TG_TABLE_NAME,
+                'op', TG_OP,
+                'old', CASE WHEN TG_OP IN ('UPDATE', 'DELETE') THEN row_to_json(OLD) END,
+                'new', CASE WHEN TG_OP IN ('INSERT', 'UPDATE') THEN row_to_json(NEW) END
+            )::TEXT;
+    END IF;
+
+    -- NOTIFY payloads are limited to 8000 bytes, ask for a full reload instead of failing the change
+    IF octet_length(payload) > 7900 THEN
+        payload := json_build_object('table', TG_TABLE_NAME, 'op', 'RESYNC')::TEXT;
+    END IF;
+
+    PERFORM pg_notify('chief_changes', payload);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER public_keys_notify_change
+    AFTER INSERT OR UPDATE OR DELETE ON public_keys
+    FOR EACH ROW EXECUTE FUNCTION chief_notify_change();
+CREATE TRIGGER public_keys_notify_truncate
+    AFTER TRUNCATE ON public_keys
+    FOR EACH STATEMENT EXECUTE FUNCTION chief_notify_change();
+
+CREATE TRIGGER kinds_notify_change
+    AFTER INSERT OR UPDATE OR DELETE ON kinds
+    FOR EACH ROW EXECUTE FUNCTION chief_notify_change();
+CREATE TRIGGER kinds_notify_truncate
+    AFTER TRUNCATE ON kinds
+    FOR EACH STATEMENT EXECUTE FUNCTION chief_notify_change();
+
+CREATE TRIGGER words_notify_change
+    AFTER INSERT OR UPDATE OR DELETE ON words
+    FOR EACH ROW EXECUTE FUNCTION chief_notify_change();
+CREATE TRIGGER words_notify_truncate
+    AFTER TRUNCATE ON words
+    FOR EACH STATEMENT EXECUTE FUNCTION chief_notify_change();
@@ -0,0 +1,17 @@
+-- Addresses and CIDR ranges for the IP filter. A single address is stored as a /32 or /128 network.
+
+CREATE TABLE IF NOT EXISTS ip_ranges
+(
+    id       SERIAL PRIMARY KEY,
+    ip_range CIDR NOT NULL
+);
+
+-- Speeds up the "range contains address" lookups of the IP filter
+CREATE INDEX IF NOT EXISTS idx_ip_ranges_ip_range ON ip_ranges USING gist (ip_range inet_ops);
+
+CREATE TRIGGER ip_ranges_notify_change
+    AFTER INSERT OR UPDATE OR DELETE ON ip_ranges
+    FOR EACH ROW EXECUTE FUNCTION chief_notify_change();
+CREATE TRIGGER ip_ranges_notify_truncate
+    AFTER TRUNCATE ON ip_ranges
+    FOR EACH STATEMENT EXECUTE FUNCTION chief_notify_change();
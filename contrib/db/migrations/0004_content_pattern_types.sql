@@ -0,0 +1,6 @@
+-- Content rules choose how they are matched: as a substring anywhere in the content (what every existing rule does),
+-- as a whole word or as a regular expression.
+
+ALTER TABLE words
+    ADD COLUMN IF NOT EXISTS pattern_type TEXT NOT NULL DEFAULT 'substring'
+        CHECK (pattern_type IN ('substring', 'word', 'regex'));
@@ -0,0 +1,5 @@
+-- Content rules can normalize the content before matching, e.g. to catch look-alike letters and zero-width characters.
+-- The column holds a comma separated list of steps, "all", or nothing at all for rules that match the content as is.
+
+ALTER TABLE words
+    ADD COLUMN IF NOT EXISTS normalize TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,70 @@
+use clap::{Parser, Subcommand};
+
+/// Default location of the configuration file when neither `--config` nor `CHIEF_CONFIG` is set
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/chief/config.toml";
+
+/// A write policy plugin for the strfry nostr relay
+#[derive(Parser)]
+#[command(name = "chief", version, about)]
+pub struct Cli {
+    /// Path to the configuration file
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "CHIEF_CONFIG",
+        default_value = DEFAULT_CONFIG_PATH
+    )]
+    pub config: String,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Clone, PartialEq, Debug)]
+pub enum Command {
+    /// Run the strfry write policy plugin loop (default)
+    Run,
+    /// Validate the configuration file and datasource, then exit
+    CheckConfig,
+    /// Apply pending Postgres schema migrations, then exit
+    Migrate {
+        /// Only report pending migrations, exit with a non-zero code if there are any
+        #[arg(long)]
+        check: bool,
+    },
+    /// Print version information and exit
+    Version,
+}
+
+impl Cli {
+    /// Returns the selected subcommand, falling back to `run` so that existing strfry setups keep working
+    pub fn command(&self) -> Command {
+        self.command.clone().unwrap_or(Command::Run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_to_run() {
+        let cli = Cli::try_parse_from(["chief"]).unwrap();
+        assert_eq!(cli.command(), Command::Run);
+    }
+
+    #[test]
+    fn test_config_flag_after_subcommand() {
+        let cli =
+            Cli::try_parse_from(["chief", "check-config", "--config", "/tmp/chief.toml"]).unwrap();
+        assert_eq!(cli.command(), Command::CheckConfig);
+        assert_eq!(cli.config, "/tmp/chief.toml");
+    }
+
+    #[test]
+    fn test_migrate_check() {
+        let cli = Cli::try_parse_from(["chief", "migrate", "--check"]).unwrap();
+        assert_eq!(cli.command(), Command::Migrate { check: true });
+    }
+}
@@ -1,4 +1,5 @@
-use serde::Deserialize;
+use crate::engine::validation::BlockedType;
+use serde::{de, Deserialize, Deserializer, Serialize};
 use std::fmt::Formatter;
 use std::fs;
 use tokio::io;
@@ -9,12 +10,29 @@ pub struct Config {
     pub filters: FiltersConfig,
     pub database: DatabaseDatasourceConfig,
     pub json: JsonDatasourceConfig,
+    #[serde(default)]
+    pub sqlite: SqliteDatasourceConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub on_error: OnErrorConfig,
+    #[serde(default)]
+    pub reload: ReloadConfig,
+    #[serde(default)]
+    pub processing: ProcessingConfig,
+    #[serde(default)]
+    pub state: StateConfig,
+    #[serde(default)]
+    pub sources: SourcesConfig,
 }
 
 #[derive(Deserialize, PartialEq, Debug)]
 pub enum DataSource {
     Json,
     Db,
+    Sqlite,
 }
 
 #[derive(Clone, Deserialize)]
@@ -23,6 +41,86 @@ pub struct FiltersConfig {
     pub kind: KindFilterConfig,
     pub content: ContentFilterConfig,
     pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub ip: IpFilterConfig,
+}
+
+impl FiltersConfig {
+    /// Returns the filters that apply to events from `source`, with every filter whose `source_types` leave it out
+    /// disabled
+    pub fn scoped(&self, source: SourceType) -> FiltersConfig {
+        let mut filters = self.clone();
+        filters.pubkey.enabled &= applies_to(&filters.pubkey.source_types, source);
+        filters.kind.enabled &= applies_to(&filters.kind.source_types, source);
+        filters.content.enabled &= applies_to(&filters.content.source_types, source);
+        filters.rate_limit.enabled &= applies_to(&filters.rate_limit.source_types, source);
+        filters.ip.enabled &= applies_to(&filters.ip.source_types, source);
+        filters
+    }
+}
+
+/// Filters without a `source_types` list apply to events from every source
+fn applies_to(source_types: &Option<Vec<SourceType>>, source: SourceType) -> bool {
+    source_types
+        .as_ref()
+        .is_none_or(|source_types| source_types.contains(&source))
+}
+
+/// How strfry got hold of an event
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Debug)]
+pub enum SourceType {
+    /// Submitted by a client connected over IPv4
+    #[serde(rename = "IP4")]
+    Ip4,
+    /// Submitted by a client connected over IPv6
+    #[serde(rename = "IP6")]
+    Ip6,
+    /// Added with `strfry import`
+    Import,
+    /// Received from another relay through `strfry stream` or the router
+    Stream,
+    /// Received from another relay through `strfry sync`
+    Sync,
+    /// A source type this version of chief doesn't know about, only filters without `source_types` apply to it
+    #[serde(skip)]
+    Unknown,
+}
+
+impl SourceType {
+    pub const ALL: [SourceType; 6] = [
+        SourceType::Ip4,
+        SourceType::Ip6,
+        SourceType::Import,
+        SourceType::Stream,
+        SourceType::Sync,
+        SourceType::Unknown,
+    ];
+
+    /// The position of the source type in [`SourceType::ALL`]
+    pub const fn index(self) -> usize {
+        match self {
+            SourceType::Ip4 => 0,
+            SourceType::Ip6 => 1,
+            SourceType::Import => 2,
+            SourceType::Stream => 3,
+            SourceType::Sync => 4,
+            SourceType::Unknown => 5,
+        }
+    }
+}
+
+impl std::fmt::Display for SourceType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SourceType::Ip4 => "IP4",
+            SourceType::Ip6 => "IP6",
+            SourceType::Import => "Import",
+            SourceType::Stream => "Stream",
+            SourceType::Sync => "Sync",
+            SourceType::Unknown => "Unknown",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 #[derive(Clone, Deserialize, PartialEq, Debug)]
@@ -31,38 +129,399 @@ pub enum FilterModeConfig {
     Whitelist,
 }
 
+impl FilterModeConfig {
+    /// Decide whether to accept or deny a value based on whether it is on the list
+    pub fn is_allowed(&self, found: bool) -> bool {
+        match self {
+            FilterModeConfig::Blacklist => !found,
+            FilterModeConfig::Whitelist => found,
+        }
+    }
+}
+
+/// The verdicts strfry understands for an event
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Action {
+    Accept,
+    Reject,
+    ShadowReject,
+}
+
+fn default_filter_action() -> Action {
+    Action::Reject
+}
+
+/// What to answer strfry when an event can't be validated, e.g. because the database is unreachable
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+pub struct OnErrorConfig {
+    pub action: Action,
+    #[serde(default = "default_on_error_msg")]
+    pub msg: String,
+}
+
+impl Default for OnErrorConfig {
+    fn default() -> Self {
+        OnErrorConfig {
+            action: Action::Reject,
+            msg: default_on_error_msg(),
+        }
+    }
+}
+
+fn default_on_error_msg() -> String {
+    String::from("error: unable to validate event, please try again later")
+}
+
 #[derive(Clone, Deserialize)]
 pub struct PubkeyFilterConfig {
     pub enabled: bool,
     pub filter_mode: FilterModeConfig,
+    /// The verdict for events blocked by this filter
+    #[serde(default = "default_filter_action")]
+    pub action: Action,
+    /// Overrides the global `on_error` policy for this filter
+    pub on_error: Option<OnErrorConfig>,
+    /// Only apply this filter to events from these sources, all sources when not set
+    pub source_types: Option<Vec<SourceType>>,
 }
 
+/// Filters on the address of the client that submitted the event. Events strfry got through an import, a stream or a
+/// sync have no address and are never blocked by this filter.
 #[derive(Clone, Deserialize)]
-pub struct KindFilterConfig {
+pub struct IpFilterConfig {
     pub enabled: bool,
     pub filter_mode: FilterModeConfig,
+    /// The verdict for events blocked by this filter
+    #[serde(default = "default_filter_action")]
+    pub action: Action,
+    /// Overrides the global `on_error` policy for this filter
+    pub on_error: Option<OnErrorConfig>,
+    /// Only apply this filter to events from these sources, all sources when not set
+    pub source_types: Option<Vec<SourceType>>,
+}
+
+impl Default for IpFilterConfig {
+    fn default() -> Self {
+        IpFilterConfig {
+            enabled: false,
+            filter_mode: FilterModeConfig::Blacklist,
+            action: default_filter_action(),
+            on_error: None,
+            source_types: None,
+        }
+    }
 }
 
 #[derive(Clone, Deserialize)]
+pub struct KindFilterConfig {
+    pub enabled: bool,
+    pub filter_mode: FilterModeConfig,
+    /// The verdict for events blocked by this filter
+    #[serde(default = "default_filter_action")]
+    pub action: Action,
+    /// Overrides the global `on_error` policy for this filter
+    pub on_error: Option<OnErrorConfig>,
+    /// Only apply this filter to events from these sources, all sources when not set
+    pub source_types: Option<Vec<SourceType>>,
+}
+
+#[derive(Clone, Deserialize, PartialEq, Debug)]
 pub struct RateLimitConfig {
     pub enabled: bool,
     pub max_events: u32,
     pub time_window: u32, // in seconds
+    /// The verdict for events blocked by this filter
+    #[serde(default = "default_filter_action")]
+    pub action: Action,
+    /// How events are counted, see [`RateLimitAlgorithmConfig`]
+    #[serde(default)]
+    pub algorithm: RateLimitAlgorithmConfig,
+    /// Size of the token bucket, i.e. the largest burst allowed. Defaults to `max_events`.
+    #[serde(default, deserialize_with = "deserialize_burst")]
+    pub burst: Option<u32>,
+    /// Events per second added back to the token bucket. Defaults to `max_events` per `time_window`.
+    #[serde(default, deserialize_with = "deserialize_refill_rate")]
+    pub refill_rate: Option<f64>,
+    /// Whether events are counted per public key, per source IP address or both
+    #[serde(default)]
+    pub key: RateLimitKeyConfig,
+    /// IPv6 addresses are counted per network with this prefix length, e.g. 64 or 48. 128 counts every address.
+    #[serde(default = "default_ipv6_prefix")]
+    pub ipv6_prefix: u8,
+    /// Separate limits for some kinds, the settings above apply to all other kinds
+    #[serde(default)]
+    pub rules: Vec<RateLimitRuleConfig>,
+    /// How many counters are tracked at most, the least recently used one is dropped beyond that. 0 means no limit.
+    #[serde(default = "default_rate_limit_max_entries")]
+    pub max_entries: usize,
+    /// How often counters of expired time windows are removed (in seconds), 0 disables sweeping
+    #[serde(default = "default_rate_limit_sweep_interval")]
+    pub sweep_interval: u64,
+    /// Only count events from these sources, all sources when not set
+    pub source_types: Option<Vec<SourceType>>,
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKeyConfig {
+    #[default]
+    Pubkey,
+    /// Only applies to events submitted by clients, i.e. with the IP4 or IP6 source type
+    Ip,
+    PubkeyAndIp,
+}
+
+fn default_ipv6_prefix() -> u8 {
+    128
+}
+
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+pub struct RateLimitRuleConfig {
+    /// The kinds this rule applies to, either single kinds or ranges like "20000-29999". The first matching rule wins.
+    pub kinds: Vec<KindRange>,
+    pub max_events: u32,
+    pub time_window: u32, // in seconds
+    /// Defaults to the algorithm of the rate limit filter
+    pub algorithm: Option<RateLimitAlgorithmConfig>,
+    #[serde(default, deserialize_with = "deserialize_burst")]
+    pub burst: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_refill_rate")]
+    pub refill_rate: Option<f64>,
+}
+
+/// A token bucket without room for a single event would block everything
+fn deserialize_burst<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    let burst = Option::<u32>::deserialize(deserializer)?;
+    if burst == Some(0) {
+        return Err(de::Error::custom("burst must be at least 1"));
+    }
+    Ok(burst)
+}
+
+/// A token bucket that isn't refilled at a positive, finite rate never lets events through again, and never expires
+fn deserialize_refill_rate<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    let refill_rate = Option::<f64>::deserialize(deserializer)?;
+    match refill_rate {
+        Some(rate) if !(rate.is_finite() && rate > 0.0) => Err(de::Error::custom(format!(
+            "refill_rate must be a positive number of events per second, got {}",
+            rate
+        ))),
+        _ => Ok(refill_rate),
+    }
+}
+
+/// An inclusive range of event kinds, written as a single kind or as "start-end"
+#[derive(Clone, Copy, Deserialize, PartialEq, Debug)]
+#[serde(try_from = "KindRangeValue")]
+pub struct KindRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl KindRange {
+    pub fn contains(&self, kind: u32) -> bool {
+        (self.start..=self.end).contains(&kind)
+    }
+}
+
+impl std::fmt::Display for KindRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KindRangeValue {
+    Kind(u32),
+    Range(String),
+}
+
+impl TryFrom<KindRangeValue> for KindRange {
+    type Error = String;
+
+    fn try_from(value: KindRangeValue) -> Result<Self, Self::Error> {
+        let range = match value {
+            KindRangeValue::Kind(kind) => {
+                return Ok(KindRange {
+                    start: kind,
+                    end: kind,
+                })
+            }
+            KindRangeValue::Range(range) => range,
+        };
+        let parse = |kind: &str| {
+            kind.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("invalid kind range \"{}\"", range))
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => (parse(&range)?, parse(&range)?),
+        };
+        if start > end {
+            return Err(format!(
+                "invalid kind range \"{}\", start is after end",
+                range
+            ));
+        }
+        Ok(KindRange { start, end })
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAlgorithmConfig {
+    /// Counts events in a window that starts with the first event after the previous window expired
+    #[default]
+    Fixed,
+    /// Counts the events in the `time_window` before every event
+    SlidingLog,
+    /// Allows bursts of up to `burst` events and refills them at `refill_rate`
+    TokenBucket,
+}
+
+fn default_rate_limit_max_entries() -> usize {
+    100_000
+}
+
+fn default_rate_limit_sweep_interval() -> u64 {
+    60
 }
 
 #[derive(Clone, Deserialize)]
 pub struct ContentFilterConfig {
     pub enabled: bool,
     pub validated_kinds: Vec<u32>,
+    /// Match the content rules case-sensitively, every datasource ignores case by default
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// The verdict for events blocked by this filter
+    #[serde(default = "default_filter_action")]
+    pub action: Action,
+    /// Overrides the global `on_error` policy for this filter
+    pub on_error: Option<OnErrorConfig>,
+    /// Only apply this filter to events from these sources, all sources when not set
+    pub source_types: Option<Vec<SourceType>>,
+    /// Tags and metadata fields that are matched against the content rules as well, per kind
+    #[serde(default)]
+    pub fields: Vec<ContentFieldsConfig>,
 }
 
-#[derive(Deserialize)]
+/// Fields besides the content that the content filter scans for events of some kinds
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+pub struct ContentFieldsConfig {
+    /// Single kinds or ranges like "30000-39999", the fields are only scanned if the content filter validates the kind
+    pub kinds: Vec<KindRange>,
+    /// Names of the tags whose value is scanned, e.g. "subject" or "t"
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Fields of the JSON object in the content, e.g. "name" and "about" of kind 0 metadata
+    #[serde(default)]
+    pub metadata: Vec<String>,
+}
+
+#[derive(Clone, Deserialize)]
 pub struct DatabaseDatasourceConfig {
+    /// Full connection string, either as a `postgresql://` URL or as `key=value` pairs.
+    /// The fields below override the matching parts of it when they are not empty.
+    pub url: Option<String>,
+    /// Host name, IP address or, when it starts with a `/`, the directory of a Unix socket
+    #[serde(default)]
     pub host: String,
+    #[serde(default)]
     pub port: String,
+    #[serde(default)]
     pub user: String,
+    #[serde(default)]
     pub password: String,
+    /// Read the password from this file instead, takes precedence over `password_env` and `password`
+    pub password_file: Option<String>,
+    /// Read the password from this environment variable instead, takes precedence over `password`
+    pub password_env: Option<String>,
+    #[serde(default)]
     pub dbname: String,
+    /// Falls back to the `sslmode` of `url`, or `disable` when neither is set
+    pub sslmode: Option<SslModeConfig>,
+    /// PEM file with the certificate authorities to trust, in addition to the system ones
+    pub ca_cert: Option<String>,
+    /// PEM files with a certificate and PKCS#8 key to authenticate chief to the server
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    /// Apply pending schema migrations when chief starts instead of refusing to start with them
+    #[serde(default)]
+    pub migrate_on_startup: bool,
+    /// Maximum number of open connections
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+    /// In seconds, also bounds waiting for a free connection
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// In seconds, covers running the query once a connection is checked out
+    #[serde(default = "default_query_timeout")]
+    pub query_timeout: u64,
+    /// After a failed connection attempt, queries fail immediately for this long (in seconds).
+    /// The wait doubles with every failed attempt up to `backoff_max`.
+    #[serde(default = "default_backoff_min")]
+    pub backoff_min: u64,
+    #[serde(default = "default_backoff_max")]
+    pub backoff_max: u64,
+    /// Run the public key, kind and content checks in a single query per event
+    #[serde(default)]
+    pub combined_query: bool,
+    /// Keep the lists in memory instead of querying the database for every event
+    #[serde(default)]
+    pub cache: bool,
+    /// How often the in-memory lists are reloaded completely (in seconds), on top of the change notifications. 0 turns
+    /// the periodic reload off.
+    #[serde(default = "default_cache_resync_interval")]
+    pub cache_resync_interval: u64,
+}
+
+fn default_pool_size() -> usize {
+    4
+}
+
+fn default_connect_timeout() -> u64 {
+    5
+}
+
+fn default_query_timeout() -> u64 {
+    2
+}
+
+fn default_backoff_min() -> u64 {
+    1
+}
+
+fn default_backoff_max() -> u64 {
+    30
+}
+
+fn default_cache_resync_interval() -> u64 {
+    300
+}
+
+/// TLS modes as known from libpq
+#[derive(Clone, Copy, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslModeConfig {
+    Disable,
+    /// Use TLS when the server supports it, without verifying the certificate
+    Prefer,
+    /// Always use TLS, without verifying the certificate
+    Require,
+    /// Always use TLS and verify the certificate chain, but not the host name
+    VerifyCa,
+    /// Always use TLS and verify both the certificate chain and the host name
+    VerifyFull,
 }
 
 #[derive(Deserialize)]
@@ -70,6 +529,152 @@ pub struct JsonDatasourceConfig {
     pub file_path: String,
 }
 
+#[derive(Deserialize, Default)]
+pub struct SqliteDatasourceConfig {
+    pub file_path: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub level: LogLevelConfig,
+    #[serde(default)]
+    pub format: LogFormatConfig,
+    /// Log to this file instead of stderr when set
+    pub file_path: Option<String>,
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Debug, Default)]
+pub enum LogLevelConfig {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevelConfig> for tracing::Level {
+    fn from(level: LogLevelConfig) -> Self {
+        match level {
+            LogLevelConfig::Error => tracing::Level::ERROR,
+            LogLevelConfig::Warn => tracing::Level::WARN,
+            LogLevelConfig::Info => tracing::Level::INFO,
+            LogLevelConfig::Debug => tracing::Level::DEBUG,
+            LogLevelConfig::Trace => tracing::Level::TRACE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Debug, Default)]
+pub enum LogFormatConfig {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ReloadConfig {
+    /// Reload automatically when the config file or the JSON datasource file changes, SIGHUP always works
+    #[serde(default)]
+    pub watch_files: bool,
+}
+
+#[derive(Deserialize)]
+pub struct MetricsConfig {
+    /// How often the metrics are written to the log (in seconds), 0 disables reporting
+    #[serde(default = "default_metrics_log_interval")]
+    pub log_interval: u64,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            log_interval: default_metrics_log_interval(),
+        }
+    }
+}
+
+fn default_metrics_log_interval() -> u64 {
+    300
+}
+
+#[derive(Deserialize)]
+pub struct ProcessingConfig {
+    /// How many events are validated at the same time, 1 handles them strictly one after the other
+    #[serde(default = "default_max_in_flight")]
+    pub max_in_flight: usize,
+    /// How long to wait for the events in flight on shutdown (in seconds) before giving up on them
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout: u64,
+}
+
+impl Default for ProcessingConfig {
+    fn default() -> Self {
+        ProcessingConfig {
+            max_in_flight: default_max_in_flight(),
+            drain_timeout: default_drain_timeout(),
+        }
+    }
+}
+
+fn default_max_in_flight() -> usize {
+    64
+}
+
+fn default_drain_timeout() -> u64 {
+    10
+}
+
+#[derive(Deserialize, Default)]
+pub struct StateConfig {
+    /// Save the rate limiter counters to this file on shutdown and restore them on startup when set
+    pub file_path: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct SourcesConfig {
+    /// Events streamed from these relays are accepted without running any filter
+    #[serde(default)]
+    pub trusted_streams: Vec<String>,
+}
+
+impl SourcesConfig {
+    /// Whether the event comes from a stream of a trusted relay, `source_info` is the relay's URL
+    pub fn is_trusted(&self, source_type: SourceType, source_info: &str) -> bool {
+        source_type == SourceType::Stream
+            && self
+                .trusted_streams
+                .iter()
+                .any(|relay| relay.trim_end_matches('/') == source_info.trim_end_matches('/'))
+    }
+}
+
+impl Config {
+    /// Returns the verdict configured for events blocked by a filter
+    pub fn action_for(&self, filter: &BlockedType) -> Action {
+        match filter {
+            BlockedType::Pubkey => self.filters.pubkey.action,
+            BlockedType::Kind => self.filters.kind.action,
+            BlockedType::Word(_) => self.filters.content.action,
+            BlockedType::RateLimit => self.filters.rate_limit.action,
+            BlockedType::Ip => self.filters.ip.action,
+        }
+    }
+
+    /// Returns the error policy for a filter, falling back to the global `on_error` section when the filter has none
+    pub fn on_error_for(&self, filter: &BlockedType) -> &OnErrorConfig {
+        let filter_on_error = match filter {
+            BlockedType::Pubkey => self.filters.pubkey.on_error.as_ref(),
+            BlockedType::Kind => self.filters.kind.on_error.as_ref(),
+            BlockedType::Word(_) => self.filters.content.on_error.as_ref(),
+            BlockedType::RateLimit => None,
+            BlockedType::Ip => self.filters.ip.on_error.as_ref(),
+        };
+        filter_on_error.unwrap_or(&self.on_error)
+    }
+}
+
 /// Load TOML config file
 pub fn load_config(filename: &str) -> Result<Config, ConfigError> {
     let content = fs::read_to_string(filename).map_err(ConfigError::ReadError)?;
@@ -91,10 +696,14 @@ impl std::fmt::Display for ConfigError {
         }
     }
 }
+
+impl std::error::Error for ConfigError {}
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
-    use crate::engine::validation::JsonDataSource;
+    use crate::engine::content::PatternType;
+    use crate::engine::validation::{ContentField, JsonDataSource};
     use std::env;
     use std::path::PathBuf;
 
@@ -121,17 +730,64 @@ mod tests {
         assert_eq!(config.filters.rate_limit.enabled, false);
         assert_eq!(config.filters.rate_limit.max_events, 10);
         assert_eq!(config.filters.rate_limit.time_window, 60);
+        assert_eq!(
+            config.filters.rate_limit.algorithm,
+            RateLimitAlgorithmConfig::Fixed
+        );
+        assert!(!config.filters.ip.enabled);
+        assert_eq!(config.filters.rate_limit.key, RateLimitKeyConfig::Pubkey);
+        assert_eq!(config.filters.rate_limit.ipv6_prefix, 128);
+        assert!(config.filters.rate_limit.rules.is_empty());
+        assert_eq!(config.filters.rate_limit.max_entries, 100_000);
+        assert_eq!(config.filters.rate_limit.sweep_interval, 60);
 
         assert_eq!(config.filters.content.enabled, false);
         assert_eq!(config.filters.content.validated_kinds, [1]);
+        assert!(!config.filters.content.case_sensitive);
+        assert!(config.filters.content.fields.is_empty());
 
         assert_eq!(config.database.host, "localhost");
         assert_eq!(config.database.port, "5432");
         assert_eq!(config.database.user, "chief");
         assert_eq!(config.database.password, "changeme");
         assert_eq!(config.database.dbname, "chief");
+        assert!(config.database.url.is_none());
+        assert!(config.database.sslmode.is_none());
+        assert_eq!(config.database.pool_size, 8);
+        assert_eq!(config.database.connect_timeout, 5);
+        assert_eq!(config.database.query_timeout, 1);
+        assert_eq!(config.database.backoff_min, 1);
+        assert_eq!(config.database.backoff_max, 30);
+        assert!(!config.database.combined_query);
+        assert!(config.database.cache);
+        assert_eq!(config.database.cache_resync_interval, 300);
+        assert!(config.sources.trusted_streams.is_empty());
+        assert_eq!(config.processing.max_in_flight, 16);
+        assert_eq!(config.processing.drain_timeout, 10);
+        assert_eq!(
+            config.state.file_path.as_deref(),
+            Some("/var/lib/chief/state.json")
+        );
 
         assert_eq!(config.json.file_path, "");
+
+        assert!(!config.reload.watch_files);
+
+        assert_eq!(config.on_error.action, Action::Accept);
+        assert_eq!(config.on_error.msg, default_on_error_msg());
+        assert_eq!(
+            config.on_error_for(&BlockedType::Pubkey),
+            &OnErrorConfig {
+                action: Action::Reject,
+                msg: String::from("restricted: unable to verify public key"),
+            }
+        );
+        assert_eq!(config.on_error_for(&BlockedType::Kind), &config.on_error);
+
+        // The logging section is optional and falls back to info level text logs on stderr
+        assert_eq!(config.logging.level, LogLevelConfig::Info);
+        assert_eq!(config.logging.format, LogFormatConfig::Text);
+        assert!(config.logging.file_path.is_none());
     }
 
     #[test]
@@ -157,9 +813,67 @@ mod tests {
         assert_eq!(config.filters.rate_limit.enabled, false);
         assert_eq!(config.filters.rate_limit.max_events, 10);
         assert_eq!(config.filters.rate_limit.time_window, 60);
+        assert_eq!(
+            config.filters.rate_limit.key,
+            RateLimitKeyConfig::PubkeyAndIp
+        );
+        assert_eq!(config.filters.rate_limit.ipv6_prefix, 64);
+        assert_eq!(
+            config.filters.rate_limit.source_types,
+            Some(vec![SourceType::Ip4, SourceType::Ip6])
+        );
+        assert!(config.filters.pubkey.source_types.is_none());
+        assert!(config
+            .sources
+            .is_trusted(SourceType::Stream, "wss://relay.example.com"));
+        assert!(!config
+            .sources
+            .is_trusted(SourceType::Sync, "wss://relay.example.com"));
+
+        assert!(config.filters.ip.enabled);
+        assert_eq!(config.filters.ip.filter_mode, FilterModeConfig::Whitelist);
+        assert_eq!(config.action_for(&BlockedType::Ip), Action::ShadowReject);
+        assert_eq!(config.on_error_for(&BlockedType::Ip).action, Action::Accept);
+        let rule = &config.filters.rate_limit.rules[0];
+        assert_eq!(
+            rule.kinds,
+            [
+                KindRange { start: 7, end: 7 },
+                KindRange {
+                    start: 20000,
+                    end: 29999
+                }
+            ]
+        );
+        assert_eq!(rule.max_events, 30);
+        assert_eq!(rule.algorithm, Some(RateLimitAlgorithmConfig::TokenBucket));
+        assert_eq!(rule.burst, Some(10));
+        assert_eq!(rule.refill_rate, None);
 
         assert_eq!(config.filters.content.enabled, false);
         assert_eq!(config.filters.content.validated_kinds, [1]);
+        assert!(config.filters.content.case_sensitive);
+        assert_eq!(
+            config.filters.content.fields,
+            [
+                ContentFieldsConfig {
+                    kinds: vec![
+                        KindRange { start: 1, end: 1 },
+                        KindRange {
+                            start: 30000,
+                            end: 39999
+                        }
+                    ],
+                    tags: vec![String::from("subject"), String::from("t")],
+                    metadata: vec![],
+                },
+                ContentFieldsConfig {
+                    kinds: vec![KindRange { start: 0, end: 0 }],
+                    tags: vec![],
+                    metadata: vec![String::from("name"), String::from("about")],
+                }
+            ]
+        );
 
         assert_eq!(config.database.host, "");
         assert_eq!(config.database.port, "");
@@ -168,6 +882,30 @@ mod tests {
         assert_eq!(config.database.dbname, "");
 
         assert_eq!(config.json.file_path, "/etc/chief/data.json");
+
+        assert_eq!(config.on_error, OnErrorConfig::default());
+        assert!(config.reload.watch_files);
+        assert_eq!(config.processing.max_in_flight, 64);
+        assert_eq!(config.processing.drain_timeout, 10);
+        assert!(config.state.file_path.is_none());
+
+        assert_eq!(config.action_for(&BlockedType::Pubkey), Action::Reject);
+        assert_eq!(config.action_for(&BlockedType::Kind), Action::Reject);
+        assert_eq!(
+            config.action_for(&BlockedType::Word(ContentField::Content)),
+            Action::ShadowReject
+        );
+        assert_eq!(
+            config.action_for(&BlockedType::RateLimit),
+            Action::ShadowReject
+        );
+
+        assert_eq!(config.logging.level, LogLevelConfig::Debug);
+        assert_eq!(config.logging.format, LogFormatConfig::Json);
+        assert_eq!(
+            config.logging.file_path.as_deref(),
+            Some("/var/log/chief/chief.log")
+        );
     }
 
     #[test]
@@ -204,13 +942,117 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_invalid_kind_range() {
+        let parse = |kinds: &str| {
+            toml::from_str::<RateLimitRuleConfig>(&format!(
+                "kinds = {}\nmax_events = 1\ntime_window = 1",
+                kinds
+            ))
+        };
+        assert!(parse(r#"["1-a"]"#).is_err());
+        assert!(parse(r#"["30000-20000"]"#).is_err());
+        assert!(parse(r#"[" 1 - 5 ", "7"]"#).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_token_bucket() {
+        let parse = |settings: &str| {
+            toml::from_str::<RateLimitRuleConfig>(&format!(
+                "kinds = [7]\nmax_events = 1\ntime_window = 1\nalgorithm = \"token_bucket\"\n{}",
+                settings
+            ))
+        };
+        assert!(parse("burst = 0").is_err());
+        assert!(parse("refill_rate = 0.0").is_err());
+        assert!(parse("refill_rate = -1.0").is_err());
+        assert!(parse("refill_rate = nan").is_err());
+        assert!(parse("refill_rate = inf").is_err());
+        assert!(parse("burst = 1\nrefill_rate = 0.01").is_ok());
+        assert!(parse("").is_ok());
+
+        let rate_limit = toml::from_str::<RateLimitConfig>(
+            "enabled = true\nmax_events = 1\ntime_window = 1\nburst = 0",
+        );
+        assert!(rate_limit.is_err());
+    }
+
+    #[test]
+    fn test_filter_mode_is_allowed() {
+        assert!(FilterModeConfig::Blacklist.is_allowed(false));
+        assert!(!FilterModeConfig::Blacklist.is_allowed(true));
+        assert!(FilterModeConfig::Whitelist.is_allowed(true));
+        assert!(!FilterModeConfig::Whitelist.is_allowed(false));
+    }
+
+    #[test]
+    fn test_source_type_index() {
+        for (i, source) in SourceType::ALL.iter().enumerate() {
+            assert_eq!(source.index(), i);
+        }
+    }
+
+    #[test]
+    fn test_scoped_filters() {
+        let mut filters: FiltersConfig = toml::from_str(
+            r#"
+            [pubkey]
+            enabled = true
+            filter_mode = "Whitelist"
+
+            [kind]
+            enabled = true
+            filter_mode = "Blacklist"
+            source_types = ["IP4", "IP6", "Import"]
+
+            [content]
+            enabled = false
+            validated_kinds = [1]
+            source_types = ["IP4"]
+
+            [rate_limit]
+            enabled = true
+            max_events = 10
+            time_window = 60
+            source_types = ["IP4", "IP6"]
+            "#,
+        )
+        .unwrap();
+        filters.content.enabled = true;
+
+        let sync = filters.scoped(SourceType::Sync);
+        assert!(sync.pubkey.enabled);
+        assert!(!sync.kind.enabled);
+        assert!(!sync.content.enabled);
+        assert!(!sync.rate_limit.enabled);
+
+        let ip6 = filters.scoped(SourceType::Ip6);
+        assert!(ip6.kind.enabled);
+        assert!(!ip6.content.enabled);
+        assert!(ip6.rate_limit.enabled);
+
+        let unknown = filters.scoped(SourceType::Unknown);
+        assert!(unknown.pubkey.enabled);
+        assert!(!unknown.kind.enabled);
+
+        // Unknown is only for requests, a config can't name it
+        let parse = |source_types: &str| {
+            toml::from_str::<PubkeyFilterConfig>(&format!(
+                "enabled = true\nfilter_mode = \"Whitelist\"\nsource_types = {}",
+                source_types
+            ))
+        };
+        assert!(parse(r#"["IP4", "Stream"]"#).is_ok());
+        assert!(parse(r#"["Unknown"]"#).is_err());
+    }
+
     #[test]
     fn test_json_datasource() {
         let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
         let mut config_path = PathBuf::from(manifest_dir);
         config_path.push("test_resources/data.json");
         let path = config_path.to_str().unwrap();
-        let json_datasource = JsonDataSource::new_from_file(path).unwrap();
+        let json_datasource = JsonDataSource::new_from_file(path, false).unwrap();
 
         assert_eq!(json_datasource.pubkeys.len(), 1);
         assert_eq!(
@@ -221,7 +1063,11 @@ mod tests {
         assert_eq!(json_datasource.kinds.len(), 1);
         assert_eq!(json_datasource.kinds.first().unwrap(), &1u32);
 
-        assert_eq!(json_datasource.words.len(), 1);
-        assert_eq!(json_datasource.words.first().unwrap(), "etf");
+        assert_eq!(json_datasource.words.len(), 2);
+        assert_eq!(json_datasource.words[0].word, "etf");
+        assert_eq!(json_datasource.words[1].pattern_type, PatternType::Word);
+
+        assert_eq!(json_datasource.ips.len(), 2);
+        assert!(json_datasource.ips[0].contains("203.0.113.7".parse().unwrap()));
     }
 }
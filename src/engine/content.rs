@@ -0,0 +1,719 @@
+use aho_corasick::AhoCorasick;
+use lru::LruCache;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::fmt::Formatter;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::confusable_detection::skeleton;
+
+/// How the pattern of a content rule is matched against the content of an event
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Hash, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PatternType {
+    /// The pattern occurs anywhere in the content, e.g. "etf" matches "netflix"
+    #[default]
+    Substring,
+    /// The pattern occurs as a whole word, i.e. neither preceded nor followed by a letter, digit or underscore
+    Word,
+    /// The pattern is a regular expression that matches somewhere in the content
+    Regex,
+}
+
+impl FromStr for PatternType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "substring" => Ok(PatternType::Substring),
+            "word" => Ok(PatternType::Word),
+            "regex" => Ok(PatternType::Regex),
+            _ => Err(format!("invalid pattern type \"{}\"", s)),
+        }
+    }
+}
+
+/// The steps a text goes through before a content rule is matched against it, written as a comma separated list of
+/// step names or as "all". The steps always run in the order of the fields.
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Hash, Debug, Default)]
+#[serde(try_from = "String")]
+pub struct Normalization {
+    /// Unicode compatibility normalization, e.g. fullwidth "ｅｔｆ" and "ℯ" become plain letters
+    pub nfkc: bool,
+    /// Removes zero-width and other invisible format characters
+    pub strip_format: bool,
+    /// Removes combining marks, e.g. "é" becomes "e" and "e̷t̷f̷" becomes "etf"
+    pub strip_marks: bool,
+    /// Unicode case folding, a more thorough version of lowercasing
+    pub case_fold: bool,
+    /// Replaces characters by the one they can be confused with, e.g. Cyrillic "е" by Latin "e", following the
+    /// skeleton algorithm of Unicode TR 39. Small capitals like "ᴛ" become the plain lowercase letter.
+    pub confusables: bool,
+}
+
+impl Normalization {
+    const ALL: Normalization = Normalization {
+        nfkc: true,
+        strip_format: true,
+        strip_marks: true,
+        case_fold: true,
+        confusables: true,
+    };
+
+    /// Runs the text through the enabled steps
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if *self == Normalization::default() {
+            return Cow::Borrowed(text);
+        }
+        let mut text: String = if self.nfkc {
+            text.nfkc().collect()
+        } else {
+            text.to_owned()
+        };
+        if self.strip_format {
+            text.retain(|c| !is_format_char(c));
+        }
+        if self.strip_marks {
+            text = text
+                .nfd()
+                .filter(|c| !is_combining_mark(*c))
+                .nfc()
+                .collect();
+        }
+        if self.case_fold {
+            text = caseless::default_case_fold_str(&text);
+        }
+        if self.confusables {
+            // Skeletons map some letters to small capitals, e.g. Cyrillic "т", which are read like the plain letter
+            text = skeleton(&text).map(small_capital_to_letter).collect();
+            if self.case_fold {
+                text = caseless::default_case_fold_str(&text);
+            }
+        }
+        Cow::Owned(text)
+    }
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "all" {
+            return Ok(Normalization::ALL);
+        }
+        let mut normalization = Normalization::default();
+        for step in s.split(',').map(str::trim).filter(|step| !step.is_empty()) {
+            match step {
+                "nfkc" => normalization.nfkc = true,
+                "strip_format" => normalization.strip_format = true,
+                "strip_marks" => normalization.strip_marks = true,
+                "case_fold" => normalization.case_fold = true,
+                "confusables" => normalization.confusables = true,
+                _ => return Err(format!("invalid normalization step \"{}\"", step)),
+            }
+        }
+        Ok(normalization)
+    }
+}
+
+impl TryFrom<String> for Normalization {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Invisible characters of the Unicode "format" category (Cf), like zero-width spaces and joiners, soft hyphens and
+/// bidirectional controls
+fn is_format_char(c: char) -> bool {
+    matches!(c,
+        '\u{00AD}'
+        | '\u{0600}'..='\u{0605}'
+        | '\u{061C}'
+        | '\u{06DD}'
+        | '\u{070F}'
+        | '\u{0890}'..='\u{0891}'
+        | '\u{08E2}'
+        | '\u{180E}'
+        | '\u{200B}'..='\u{200F}'
+        | '\u{202A}'..='\u{202E}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{2066}'..='\u{206F}'
+        | '\u{FEFF}'
+        | '\u{FFF9}'..='\u{FFFB}'
+        | '\u{110BD}'
+        | '\u{110CD}'
+        | '\u{13430}'..='\u{1343F}'
+        | '\u{1BCA0}'..='\u{1BCA3}'
+        | '\u{1D173}'..='\u{1D17A}'
+        | '\u{E0001}'
+        | '\u{E0020}'..='\u{E007F}'
+    )
+}
+
+fn small_capital_to_letter(c: char) -> char {
+    match c {
+        'ᴀ' => 'a',
+        'ʙ' => 'b',
+        'ᴄ' => 'c',
+        'ᴅ' => 'd',
+        'ᴇ' => 'e',
+        'ꜰ' => 'f',
+        'ɢ' => 'g',
+        'ʜ' => 'h',
+        'ɪ' => 'i',
+        'ᴊ' => 'j',
+        'ᴋ' => 'k',
+        'ʟ' => 'l',
+        'ᴍ' => 'm',
+        'ɴ' => 'n',
+        'ᴏ' => 'o',
+        'ᴘ' => 'p',
+        'ꞯ' => 'q',
+        'ʀ' => 'r',
+        'ꜱ' => 's',
+        'ᴛ' => 't',
+        'ᴜ' => 'u',
+        'ᴠ' => 'v',
+        'ᴡ' => 'w',
+        'ʏ' => 'y',
+        'ᴢ' => 'z',
+        c => c,
+    }
+}
+
+/// An entry of the content blacklist, written either as a plain string (a substring rule) or as
+/// `{"word": "...", "pattern_type": "...", "normalize": "..."}`
+#[derive(Clone, Deserialize, PartialEq, Eq, Hash, Debug)]
+#[serde(from = "ContentRuleValue")]
+pub struct ContentRule {
+    pub word: String,
+    pub pattern_type: PatternType,
+    /// Applied to the content, and to the word unless it is a regular expression, before matching
+    pub normalize: Normalization,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ContentRuleValue {
+    Word(String),
+    Rule {
+        word: String,
+        #[serde(default)]
+        pattern_type: PatternType,
+        #[serde(default)]
+        normalize: Normalization,
+    },
+}
+
+impl From<ContentRuleValue> for ContentRule {
+    fn from(value: ContentRuleValue) -> Self {
+        match value {
+            ContentRuleValue::Word(word) => ContentRule {
+                word,
+                pattern_type: PatternType::Substring,
+                normalize: Normalization::default(),
+            },
+            ContentRuleValue::Rule {
+                word,
+                pattern_type,
+                normalize,
+            } => ContentRule {
+                word,
+                pattern_type,
+                normalize,
+            },
+        }
+    }
+}
+
+/// A content blacklist that failed to compile
+#[derive(Debug)]
+pub enum PatternError {
+    /// A regex rule that isn't a valid regular expression
+    Regex { word: String, source: regex::Error },
+    /// The words are too many to build an automaton from
+    Automaton(aho_corasick::BuildError),
+}
+
+impl std::fmt::Display for PatternError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatternError::Regex { word, source } => {
+                write!(f, "invalid content pattern \"{}\": {}", word, source)
+            }
+            PatternError::Automaton(e) => write!(f, "failed to compile the content words: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PatternError::Regex { source, .. } => Some(source),
+            PatternError::Automaton(e) => Some(e),
+        }
+    }
+}
+
+/// A single compiled content rule
+#[derive(Debug)]
+struct Pattern {
+    normalize: Normalization,
+    matcher: PatternMatcher,
+}
+
+#[derive(Debug)]
+enum PatternMatcher {
+    Substring(String),
+    Word(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn compile(rule: &ContentRule, case_sensitive: bool) -> Result<Self, PatternError> {
+        let word = || fold(&rule.normalize.apply(&rule.word), case_sensitive).into_owned();
+        let matcher = match rule.pattern_type {
+            PatternType::Substring => PatternMatcher::Substring(word()),
+            PatternType::Word => PatternMatcher::Word(word()),
+            PatternType::Regex => PatternMatcher::Regex(compile_regex(rule, case_sensitive)?),
+        };
+        Ok(Pattern {
+            normalize: rule.normalize,
+            matcher,
+        })
+    }
+
+    fn is_match(&self, content: &mut Content) -> bool {
+        let (normalized, folded) = content.get(self.normalize);
+        match &self.matcher {
+            PatternMatcher::Substring(word) => folded.contains(word.as_str()),
+            PatternMatcher::Word(word) => contains_word(folded, word),
+            PatternMatcher::Regex(regex) => regex.is_match(normalized),
+        }
+    }
+}
+
+/// The content of an event in the forms the patterns need, every form is only computed once
+struct Content<'a> {
+    content: &'a str,
+    case_sensitive: bool,
+    /// The normalized and the normalized and case-folded content, per normalization
+    forms: Vec<(Normalization, String, String)>,
+}
+
+impl<'a> Content<'a> {
+    fn new(content: &'a str, case_sensitive: bool) -> Self {
+        Content {
+            content,
+            case_sensitive,
+            forms: Vec::new(),
+        }
+    }
+
+    fn get(&mut self, normalize: Normalization) -> (&str, &str) {
+        let index = match self.forms.iter().position(|(n, _, _)| *n == normalize) {
+            Some(index) => index,
+            None => {
+                let normalized = normalize.apply(self.content).into_owned();
+                let folded = fold(&normalized, self.case_sensitive).into_owned();
+                self.forms.push((normalize, normalized, folded));
+                self.forms.len() - 1
+            }
+        };
+        let (_, normalized, folded) = &self.forms[index];
+        (normalized, folded)
+    }
+}
+
+/// The content blacklist compiled for matching. Built once whenever the rules are loaded.
+#[derive(Debug, Default)]
+pub struct ContentMatcher {
+    groups: Vec<PatternGroup>,
+    case_sensitive: bool,
+}
+
+/// The rules that share a normalization. The substring and the word rules are each compiled into a single
+/// automaton, so the content is scanned once no matter how many words the blacklist has.
+#[derive(Debug)]
+struct PatternGroup {
+    normalize: Normalization,
+    substrings: Option<AhoCorasick>,
+    words: Option<AhoCorasick>,
+    regexes: Vec<Regex>,
+}
+
+impl PatternGroup {
+    fn compile(
+        normalize: Normalization,
+        rules: &[&ContentRule],
+        case_sensitive: bool,
+    ) -> Result<Self, PatternError> {
+        let words = |pattern_type| {
+            rules
+                .iter()
+                .filter(move |rule| rule.pattern_type == pattern_type)
+                .map(|rule| fold(&normalize.apply(&rule.word), case_sensitive).into_owned())
+        };
+        let automaton = |words: Vec<String>| {
+            if words.is_empty() {
+                return Ok(None);
+            }
+            AhoCorasick::new(words)
+                .map(Some)
+                .map_err(PatternError::Automaton)
+        };
+        Ok(PatternGroup {
+            normalize,
+            substrings: automaton(words(PatternType::Substring).collect())?,
+            // An empty word has no boundaries to check and never matches
+            words: automaton(words(PatternType::Word).filter(|w| !w.is_empty()).collect())?,
+            regexes: rules
+                .iter()
+                .filter(|rule| rule.pattern_type == PatternType::Regex)
+                .map(|rule| compile_regex(rule, case_sensitive))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn is_match(&self, content: &mut Content) -> bool {
+        let (normalized, folded) = content.get(self.normalize);
+        self.substrings.as_ref().is_some_and(|a| a.is_match(folded))
+            || self.words.as_ref().is_some_and(|a| {
+                a.find_overlapping_iter(folded)
+                    .any(|m| is_word_at(folded, m.start(), m.end()))
+            })
+            || self.regexes.iter().any(|regex| regex.is_match(normalized))
+    }
+}
+
+impl ContentMatcher {
+    pub fn new<'a>(
+        rules: impl IntoIterator<Item = &'a ContentRule>,
+        case_sensitive: bool,
+    ) -> Result<Self, PatternError> {
+        let mut rules_by_normalization: Vec<(Normalization, Vec<&ContentRule>)> = Vec::new();
+        for rule in rules {
+            match rules_by_normalization
+                .iter_mut()
+                .find(|(normalize, _)| *normalize == rule.normalize)
+            {
+                Some((_, rules)) => rules.push(rule),
+                None => rules_by_normalization.push((rule.normalize, vec![rule])),
+            }
+        }
+        let groups = rules_by_normalization
+            .into_iter()
+            .map(|(normalize, rules)| PatternGroup::compile(normalize, &rules, case_sensitive))
+            .collect::<Result<_, _>>()?;
+        Ok(ContentMatcher {
+            groups,
+            case_sensitive,
+        })
+    }
+
+    /// Whether any rule matches the content
+    pub fn is_match(&self, content: &str) -> bool {
+        let mut content = Content::new(content, self.case_sensitive);
+        self.groups.iter().any(|group| group.is_match(&mut content))
+    }
+}
+
+/// How many compiled rules a [`PatternCache`] keeps at first, it grows to hold the candidate rules of any one event
+const PATTERN_CACHE_SIZE: usize = 1024;
+
+/// Compiled rules for datasources that look up the candidate rules per event, so a rule is only compiled the first
+/// time it shows up rather than for every event
+pub struct PatternCache {
+    patterns: Mutex<LruCache<ContentRule, Arc<Pattern>>>,
+    case_sensitive: bool,
+}
+
+impl PatternCache {
+    pub fn new(case_sensitive: bool) -> Self {
+        PatternCache {
+            patterns: Mutex::new(LruCache::new(
+                NonZeroUsize::new(PATTERN_CACHE_SIZE).unwrap(),
+            )),
+            case_sensitive,
+        }
+    }
+
+    /// Whether any of the rules matches the content
+    pub fn is_match(&self, rules: &[ContentRule], content: &str) -> Result<bool, PatternError> {
+        // Regular expressions and rules that normalize are candidates for every event. A cache that can't hold all of
+        // them would evict each one before it is needed again and compile them all for every event.
+        {
+            let mut patterns = self.patterns.lock().unwrap();
+            if let Some(size) = NonZeroUsize::new(rules.len()) {
+                if size > patterns.cap() {
+                    patterns.resize(size);
+                }
+            }
+        }
+
+        let mut content = Content::new(content, self.case_sensitive);
+        for rule in rules {
+            if self.pattern(rule)?.is_match(&mut content) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn pattern(&self, rule: &ContentRule) -> Result<Arc<Pattern>, PatternError> {
+        if let Some(pattern) = self.patterns.lock().unwrap().get(rule) {
+            return Ok(pattern.clone());
+        }
+        let pattern = Arc::new(Pattern::compile(rule, self.case_sensitive)?);
+        self.patterns
+            .lock()
+            .unwrap()
+            .put(rule.clone(), pattern.clone());
+        Ok(pattern)
+    }
+}
+
+fn fold(text: &str, case_sensitive: bool) -> Cow<'_, str> {
+    if case_sensitive {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(text.to_lowercase())
+    }
+}
+
+fn compile_regex(rule: &ContentRule, case_sensitive: bool) -> Result<Regex, PatternError> {
+    RegexBuilder::new(&rule.word)
+        .case_insensitive(!case_sensitive)
+        .build()
+        .map_err(|source| PatternError::Regex {
+            word: rule.word.clone(),
+            source,
+        })
+}
+
+/// Whether `word` occurs in `text` without a letter, digit or underscore right before or after it
+fn contains_word(text: &str, word: &str) -> bool {
+    if word.is_empty() {
+        return false;
+    }
+    // Occurrences may overlap, a later one can still be a whole word when an earlier one isn't
+    let mut from = 0;
+    while let Some(offset) = text[from..].find(word) {
+        let start = from + offset;
+        if is_word_at(text, start, start + word.len()) {
+            return true;
+        }
+        from = start + text[start..].chars().next().map_or(1, char::len_utf8);
+    }
+    false
+}
+
+/// Whether `text[start..end]` has no letter, digit or underscore right before or after it
+fn is_word_at(text: &str, start: usize, end: usize) -> bool {
+    let before = text[..start].chars().next_back();
+    let after = text[end..].chars().next();
+    !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(word: &str, pattern_type: PatternType) -> ContentRule {
+        ContentRule {
+            word: word.to_owned(),
+            pattern_type,
+            normalize: Normalization::default(),
+        }
+    }
+
+    #[test]
+    fn test_pattern_types() {
+        let matches = |rule: ContentRule, content: &str| {
+            ContentMatcher::new([&rule], false)
+                .unwrap()
+                .is_match(content)
+        };
+
+        assert!(matches(
+            rule("etf", PatternType::Substring),
+            "I love Netflix"
+        ));
+        assert!(!matches(rule("etf", PatternType::Word), "I love Netflix"));
+        assert!(matches(rule("etf", PatternType::Word), "Buy my ETF!"));
+        assert!(matches(rule("etf", PatternType::Word), "etf"));
+        assert!(!matches(rule("etf", PatternType::Word), "etf_fund"));
+        // Word boundaries are Unicode aware
+        assert!(!matches(rule("etf", PatternType::Word), "étf"));
+        assert!(matches(rule("$etf", PatternType::Word), "only $etf, now"));
+        // Only the second, overlapping occurrence is a whole word
+        assert!(matches(rule("--", PatternType::Word), "a---"));
+        assert!(!matches(rule("", PatternType::Word), "etf"));
+        assert!(matches(
+            rule(r"bit\.ly/\w+", PatternType::Regex),
+            "see BIT.LY/abc"
+        ));
+        assert!(!matches(rule(r"^free", PatternType::Regex), "not free"));
+    }
+
+    #[test]
+    fn test_many_rules() {
+        let mut rules: Vec<ContentRule> = (0..1000)
+            .map(|i| rule(&format!("spam{}", i), PatternType::Substring))
+            .collect();
+        rules.push(rule("etf", PatternType::Word));
+        rules.push(ContentRule {
+            normalize: Normalization::ALL,
+            ..rule("nft", PatternType::Word)
+        });
+        rules.push(rule(r"bit\.ly/\w+", PatternType::Regex));
+        let matcher = ContentMatcher::new(&rules, false).unwrap();
+
+        assert!(matcher.is_match("buy SPAM999 now"));
+        assert!(matcher.is_match("an etf"));
+        assert!(!matcher.is_match("netflix"));
+        assert!(matcher.is_match("an NFТ"));
+        assert!(matcher.is_match("bit.ly/abc"));
+        assert!(!matcher.is_match("spam and ham"));
+        assert_eq!(matcher.groups.len(), 2);
+    }
+
+    #[test]
+    fn test_case_sensitive() {
+        let matcher = ContentMatcher::new([&rule("ETF", PatternType::Word)], true).unwrap();
+
+        assert!(matcher.is_match("an ETF"));
+        assert!(!matcher.is_match("an etf"));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        assert!(ContentMatcher::new([&rule("(", PatternType::Regex)], false).is_err());
+    }
+
+    #[test]
+    fn test_json_rules() {
+        let rules: Vec<ContentRule> = serde_json::from_str(
+            r#"["etf", {"word": "nft", "pattern_type": "word"}, {"word": "x"}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rules,
+            [
+                rule("etf", PatternType::Substring),
+                rule("nft", PatternType::Word),
+                rule("x", PatternType::Substring)
+            ]
+        );
+        assert!(
+            serde_json::from_str::<ContentRule>(r#"{"word": "x", "pattern_type": "glob"}"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_normalization() {
+        let normalized = |rule: ContentRule, case_sensitive: bool, content: &str| {
+            let normalized = ContentRule {
+                normalize: Normalization::ALL,
+                ..rule.clone()
+            };
+            let matches = |rule| {
+                ContentMatcher::new([&rule], case_sensitive)
+                    .unwrap()
+                    .is_match(content)
+            };
+            // Without normalization these all get through
+            assert!(!matches(rule), "{} matches without normalization", content);
+            matches(normalized)
+        };
+
+        // Cyrillic look-alikes
+        assert!(normalized(rule("etf", PatternType::Word), false, "ЕТF"));
+        assert!(normalized(rule("etf", PatternType::Word), true, "buy еtf"));
+        // Zero-width space
+        assert!(normalized(
+            rule("etf", PatternType::Word),
+            false,
+            "e\u{200B}tf"
+        ));
+        assert!(normalized(
+            rule("etf", PatternType::Word),
+            false,
+            "small caps ᴇᴛꜰ"
+        ));
+        assert!(normalized(
+            rule("bitcoin", PatternType::Word),
+            false,
+            "BІTCOІN"
+        ));
+        // Fullwidth letters
+        assert!(normalized(
+            rule("etf", PatternType::Substring),
+            false,
+            "ＥＴＦ"
+        ));
+        // Combining marks
+        assert!(normalized(
+            rule("etf", PatternType::Word),
+            false,
+            "e\u{0337}t\u{0337}f\u{0337}"
+        ));
+        assert!(normalized(
+            rule("^etf", PatternType::Regex),
+            false,
+            "е\u{200D}tf now"
+        ));
+    }
+
+    #[test]
+    fn test_parse_normalization() {
+        assert_eq!("all".parse(), Ok(Normalization::ALL));
+        assert_eq!("".parse(), Ok(Normalization::default()));
+        assert_eq!(
+            " nfkc, case_fold ".parse(),
+            Ok(Normalization {
+                nfkc: true,
+                case_fold: true,
+                ..Normalization::default()
+            })
+        );
+        assert!("nfkc,nfd".parse::<Normalization>().is_err());
+
+        let rule: ContentRule =
+            serde_json::from_str(r#"{"word": "etf", "normalize": "strip_format"}"#).unwrap();
+        assert!(rule.normalize.strip_format);
+        assert!(!rule.normalize.confusables);
+    }
+
+    #[test]
+    fn test_pattern_cache() {
+        let cache = PatternCache::new(false);
+        let rules = [rule("etf", PatternType::Word)];
+
+        assert!(cache.is_match(&rules, "an ETF").unwrap());
+        assert!(!cache.is_match(&rules, "netflix").unwrap());
+        assert_eq!(cache.patterns.lock().unwrap().len(), 1);
+
+        // Grows to keep every candidate compiled
+        let rules: Vec<ContentRule> = (0..PATTERN_CACHE_SIZE + 10)
+            .map(|i| rule(&format!("^rule{i}$"), PatternType::Regex))
+            .collect();
+        assert!(!cache.is_match(&rules, "hello").unwrap());
+        assert_eq!(
+            cache.patterns.lock().unwrap().len(),
+            PATTERN_CACHE_SIZE + 10
+        );
+    }
+}
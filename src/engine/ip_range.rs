@@ -0,0 +1,75 @@
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::fmt::Formatter;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A network in CIDR notation like "10.0.0.0/8", or a single address
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Hash, Debug)]
+#[serde(try_from = "String")]
+pub struct IpRange(IpNet);
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Dual-stack sockets report IPv4 clients as IPv4-mapped IPv6 addresses
+        self.0.contains(&ip.to_canonical())
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(network) = s.parse::<IpNet>() {
+            // Ignore host bits, "10.1.2.3/8" means the same as "10.0.0.0/8"
+            return Ok(IpRange(network.trunc()));
+        }
+        s.parse::<IpAddr>()
+            .map(|ip| IpRange(IpNet::from(ip)))
+            .map_err(|_| format!("invalid IP address or range \"{}\"", s))
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for IpRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(range: &str, ip: &str) -> bool {
+        range
+            .parse::<IpRange>()
+            .unwrap()
+            .contains(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn test_contains() {
+        assert!(contains("10.0.0.0/8", "10.20.30.40"));
+        assert!(contains("10.1.2.3/8", "10.20.30.40"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.1"));
+        assert!(contains("192.0.2.1", "192.0.2.1"));
+        assert!(!contains("192.0.2.1", "192.0.2.2"));
+        assert!(contains("2001:db8::/32", "2001:db8:1::1"));
+        assert!(contains("192.0.2.0/24", "::ffff:192.0.2.9"));
+    }
+
+    #[test]
+    fn test_invalid_range() {
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("example.com".parse::<IpRange>().is_err());
+    }
+}
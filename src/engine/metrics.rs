@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::info;
+
+/// Process wide counters, reported periodically through the logs
+pub static METRICS: Metrics = Metrics::new();
+
+/// A monotonically increasing counter
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn incr(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that can go up and down
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    const fn new() -> Self {
+        Gauge(AtomicU64::new(0))
+    }
+
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Metrics {
+    pub requests: Counter,
+    pub accepted: Counter,
+    pub rejected: Counter,
+    pub parse_errors: Counter,
+    pub read_errors: Counter,
+    pub write_errors: Counter,
+    pub validation_errors: Counter,
+    pub db_errors: Counter,
+    /// 1 while the Postgres datasource is reachable, 0 otherwise
+    pub db_healthy: Gauge,
+    /// Number of counters the rate limiter currently tracks
+    pub rate_limit_entries: Gauge,
+    /// Rate limiter counters dropped to stay within `max_entries`
+    pub rate_limit_evictions: Counter,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            requests: Counter::new(),
+            accepted: Counter::new(),
+            rejected: Counter::new(),
+            parse_errors: Counter::new(),
+            read_errors: Counter::new(),
+            write_errors: Counter::new(),
+            validation_errors: Counter::new(),
+            db_errors: Counter::new(),
+            db_healthy: Gauge::new(),
+            rate_limit_entries: Gauge::new(),
+            rate_limit_evictions: Counter::new(),
+        }
+    }
+
+    /// Writes the current value of every metric to the log
+    pub fn log(&self) {
+        info!(
+            requests = self.requests.get(),
+            accepted = self.accepted.get(),
+            rejected = self.rejected.get(),
+            parse_errors = self.parse_errors.get(),
+            read_errors = self.read_errors.get(),
+            write_errors = self.write_errors.get(),
+            validation_errors = self.validation_errors.get(),
+            db_errors = self.db_errors.get(),
+            db_healthy = self.db_healthy.get(),
+            rate_limit_entries = self.rate_limit_entries.get(),
+            rate_limit_evictions = self.rate_limit_evictions.get(),
+            "metrics"
+        );
+    }
+}
+
+/// Logs the metrics every `interval` until the process exits. An interval of zero disables reporting.
+pub fn spawn_reporter(interval: Duration) {
+    if interval.is_zero() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately, skip it so we don't report an empty snapshot at startup
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            METRICS.log();
+        }
+    });
+}
@@ -0,0 +1,143 @@
+use std::error::Error;
+use tokio_postgres::Client;
+use tracing::warn;
+
+/// A numbered schema change for the Postgres datasource
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Every migration chief knows about, in the order they have to be applied. Never edit a released migration,
+/// add a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("../../contrib/db/migrations/0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "change_notifications",
+        sql: include_str!("../../contrib/db/migrations/0002_change_notifications.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "ip_ranges",
+        sql: include_str!("../../contrib/db/migrations/0003_ip_ranges.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "content_pattern_types",
+        sql: include_str!("../../contrib/db/migrations/0004_content_pattern_types.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "content_normalization",
+        sql: include_str!("../../contrib/db/migrations/0005_content_normalization.sql"),
+    },
+];
+
+const CREATE_VERSION_TABLE: &str = "
+CREATE TABLE chief_schema_migrations
+(
+    version    INTEGER PRIMARY KEY,
+    name       TEXT        NOT NULL,
+    applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+)";
+
+/// Arbitrary key for the advisory lock that keeps several chief instances from migrating the same database at once
+const MIGRATION_LOCK_KEY: i64 = 0x0063_6869_6566; // "chief" in ASCII
+
+/// Returns the migrations that haven't been applied to the database yet
+pub async fn pending(
+    client: &Client,
+) -> Result<Vec<&'static Migration>, Box<dyn Error + Send + Sync>> {
+    let current = if version_table_exists(client).await? {
+        current_version(client).await?
+    } else {
+        0
+    };
+
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > current)
+        .collect())
+}
+
+/// Applies all pending migrations, each one in its own transaction. Returns the migrations that were applied.
+pub async fn migrate(
+    client: &mut Client,
+) -> Result<Vec<&'static Migration>, Box<dyn Error + Send + Sync>> {
+    client
+        .execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK_KEY])
+        .await?;
+    let result = apply_pending(client).await;
+    // The lock is released with the session anyway, a failed unlock mustn't hide the outcome of the migrations
+    if let Err(e) = client
+        .execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK_KEY])
+        .await
+    {
+        warn!(error = %e, "failed to release the migration lock");
+    }
+    result
+}
+
+async fn apply_pending(
+    client: &mut Client,
+) -> Result<Vec<&'static Migration>, Box<dyn Error + Send + Sync>> {
+    if !version_table_exists(client).await? {
+        client.batch_execute(CREATE_VERSION_TABLE).await?;
+    }
+
+    // Read the version only now that we hold the lock, another instance may have just migrated
+    let current = current_version(client).await?;
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let transaction = client.transaction().await?;
+        transaction.batch_execute(migration.sql).await?;
+        transaction
+            .execute(
+                "INSERT INTO chief_schema_migrations(version, name) VALUES ($1, $2)",
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+        transaction.commit().await?;
+        applied.push(migration);
+    }
+
+    Ok(applied)
+}
+
+async fn version_table_exists(client: &Client) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let row = client
+        .query_one(
+            "SELECT to_regclass('chief_schema_migrations') IS NOT NULL",
+            &[],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+async fn current_version(client: &Client) -> Result<i32, Box<dyn Error + Send + Sync>> {
+    let row = client
+        .query_one(
+            "SELECT COALESCE(MAX(version), 0) FROM chief_schema_migrations",
+            &[],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_numbered_in_order() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i32 + 1, "{}", migration.name);
+        }
+    }
+}
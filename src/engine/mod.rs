@@ -1,3 +1,13 @@
 pub mod config;
+pub mod content;
+pub mod datasource;
+pub mod ip_range;
+pub mod metrics;
+pub mod migrations;
+pub mod postgres;
+pub mod postgres_cache;
 pub mod ratelimit;
+pub mod sqlite;
+pub mod state;
+pub mod state_file;
 pub mod validation;
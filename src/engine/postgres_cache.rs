@@ -0,0 +1,564 @@
+use crate::engine::config::{DatabaseDatasourceConfig, FilterModeConfig};
+use crate::engine::content::{ContentMatcher, ContentRule, Normalization, PatternType};
+use crate::engine::ip_range::IpRange;
+use crate::engine::metrics::METRICS;
+use crate::engine::postgres::{postgres_config, tls_connector};
+use crate::engine::validation::{ValidationDataSource, ValidationFuture};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::poll_fn;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::task::JoinHandle;
+use tokio::time::Interval;
+use tokio_postgres::{AsyncMessage, Client};
+use tracing::{debug, error, info, warn};
+
+/// The channel the triggers installed by the `change_notifications` migration publish to
+const CHANNEL: &str = "chief_changes";
+
+/// How long to wait before reconnecting after the listener connection failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Serves the filter lists from an in-memory copy of the Postgres tables.
+///
+/// A background task keeps the copy up to date by listening for change notifications and reloads it completely every
+/// `cache_resync_interval` seconds in case a notification got lost. While the database is unreachable the last copy
+/// keeps being used.
+pub struct CachedPostgresDataSource {
+    snapshot: Arc<RwLock<Snapshot>>,
+    listener: JoinHandle<()>,
+}
+
+impl CachedPostgresDataSource {
+    /// Loads the tables and starts listening for changes. Fails if the initial load fails.
+    pub async fn new(
+        config: &DatabaseDatasourceConfig,
+        case_sensitive: bool,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut listener = Listener::connect(config).await?;
+        let snapshot = Snapshot::load(&listener.client, case_sensitive).await?;
+        info!(
+            pubkeys = snapshot.pubkeys.len(),
+            kinds = snapshot.kinds.len(),
+            words = snapshot.words.len(),
+            ip_ranges = snapshot.ip_ranges.len(),
+            "loaded database snapshot"
+        );
+        let snapshot = Arc::new(RwLock::new(snapshot));
+
+        let pg_config = config.clone();
+        let task_snapshot = snapshot.clone();
+        let resync_interval = Duration::from_secs(config.cache_resync_interval);
+        let listener = tokio::spawn(async move {
+            loop {
+                let e = listener.run(&task_snapshot, resync_interval).await;
+                METRICS.db_healthy.set(0);
+                error!(error = %e, "lost database change notifications, serving the cached lists");
+
+                listener = loop {
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    match Listener::connect(&pg_config).await {
+                        Ok(listener) => break listener,
+                        Err(e) => warn!(error = %e, "database still unavailable"),
+                    }
+                };
+                // Changes made while we were disconnected were not announced to us
+                match Snapshot::load(&listener.client, case_sensitive).await {
+                    Ok(fresh) => {
+                        *task_snapshot.write().unwrap() = fresh;
+                        METRICS.db_healthy.set(1);
+                        info!("database connection restored, reloaded snapshot");
+                    }
+                    Err(e) => warn!(error = %e, "unable to reload database snapshot"),
+                }
+            }
+        });
+        METRICS.db_healthy.set(1);
+
+        Ok(CachedPostgresDataSource { snapshot, listener })
+    }
+}
+
+impl Drop for CachedPostgresDataSource {
+    fn drop(&mut self) {
+        // Stop listening once a reload replaced this datasource
+        self.listener.abort();
+    }
+}
+
+impl ValidationDataSource for CachedPostgresDataSource {
+    fn is_pubkey_allowed(
+        &self,
+        pubkey: &str,
+        filter_mode: FilterModeConfig,
+    ) -> ValidationFuture<'_> {
+        let found = self.snapshot.read().unwrap().pubkeys.contains(pubkey);
+        Box::pin(async move { Ok(filter_mode.is_allowed(found)) })
+    }
+
+    fn is_kind_allowed(&self, kind: u32, filter_mode: FilterModeConfig) -> ValidationFuture<'_> {
+        // Kinds are stored as INTEGER in the database
+        let found = self.snapshot.read().unwrap().kinds.contains(&(kind as i32));
+        Box::pin(async move { Ok(filter_mode.is_allowed(found)) })
+    }
+
+    fn is_content_allowed(&self, content: &str) -> ValidationFuture<'_> {
+        let found = match &self.snapshot.read().unwrap().content {
+            Ok(matcher) => Ok(matcher.is_match(content)),
+            Err(e) => Err(e.clone()),
+        };
+        Box::pin(async move { Ok(!found?) })
+    }
+
+    fn is_ip_allowed(&self, ip: IpAddr, filter_mode: FilterModeConfig) -> ValidationFuture<'_> {
+        let found = self
+            .snapshot
+            .read()
+            .unwrap()
+            .ip_ranges
+            .values()
+            .any(|range| range.contains(ip));
+        Box::pin(async move { Ok(filter_mode.is_allowed(found)) })
+    }
+}
+
+/// In-memory copy of the filter tables
+struct Snapshot {
+    pubkeys: Rows<String>,
+    kinds: Rows<i32>,
+    words: Rows<ContentRule>,
+    ip_ranges: Rows<IpRange>,
+    /// `words` compiled for matching. An invalid rule fails every content check until it is fixed.
+    content: Result<ContentMatcher, String>,
+    case_sensitive: bool,
+}
+
+impl Snapshot {
+    fn new(case_sensitive: bool) -> Self {
+        Snapshot {
+            pubkeys: Rows::default(),
+            kinds: Rows::default(),
+            words: Rows::default(),
+            ip_ranges: Rows::default(),
+            content: Ok(ContentMatcher::default()),
+            case_sensitive,
+        }
+    }
+
+    async fn load(
+        client: &Client,
+        case_sensitive: bool,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut snapshot = Snapshot::new(case_sensitive);
+        for row in client
+            .query("SELECT id, publickey FROM public_keys", &[])
+            .await?
+        {
+            snapshot.pubkeys.insert(row.get(0), row.get(1));
+        }
+        for row in client.query("SELECT id, kind FROM kinds", &[]).await? {
+            snapshot.kinds.insert(row.get(0), row.get(1));
+        }
+        for row in client
+            .query("SELECT id, word, pattern_type, normalize FROM words", &[])
+            .await?
+        {
+            let rule = ContentRule {
+                word: row.get(1),
+                pattern_type: row.get::<_, &str>(2).parse()?,
+                normalize: row.get::<_, &str>(3).parse()?,
+            };
+            snapshot.words.insert(row.get(0), rule);
+        }
+        snapshot.content = compile_content(snapshot.words.values(), case_sensitive);
+        for row in client
+            .query("SELECT id, ip_range::TEXT FROM ip_ranges", &[])
+            .await?
+        {
+            snapshot
+                .ip_ranges
+                .insert(row.get(0), row.get::<_, &str>(1).parse()?);
+        }
+        Ok(snapshot)
+    }
+
+    /// Applies a change notification, returns false if the snapshot has to be reloaded completely. Changes to the words
+    /// leave `content` as it is, see [`apply_changes`].
+    fn apply(&mut self, change: &Change) -> bool {
+        match change.table.as_str() {
+            "public_keys" => apply_change(&mut self.pubkeys, change, |row| {
+                row.get("publickey")?.as_str().map(str::to_owned)
+            }),
+            "kinds" => apply_change(&mut self.kinds, change, |row| {
+                i32::try_from(row.get("kind")?.as_i64()?).ok()
+            }),
+            "words" => apply_change(&mut self.words, change, |row| {
+                Some(ContentRule {
+                    word: row.get("word")?.as_str()?.to_owned(),
+                    pattern_type: match row.get("pattern_type") {
+                        Some(pattern_type) => pattern_type.as_str()?.parse().ok()?,
+                        None => PatternType::Substring,
+                    },
+                    normalize: match row.get("normalize") {
+                        Some(normalize) => normalize.as_str()?.parse().ok()?,
+                        None => Normalization::default(),
+                    },
+                })
+            }),
+            "ip_ranges" => apply_change(&mut self.ip_ranges, change, |row| {
+                row.get("ip_range")?.as_str()?.parse().ok()
+            }),
+            _ => true,
+        }
+    }
+}
+
+/// Applies a batch of change notifications, returns false if the snapshot has to be reloaded completely.
+///
+/// A bulk change sends one notification per row, so the content rules are only compiled once for the whole batch, and
+/// outside the write lock so validations aren't blocked while they compile. Only the listener modifies the snapshot,
+/// the words can't change in between.
+fn apply_changes(snapshot: &RwLock<Snapshot>, payloads: &[String]) -> bool {
+    let mut words_changed = false;
+    {
+        let mut snapshot = snapshot.write().unwrap();
+        for payload in payloads {
+            let change = match serde_json::from_str::<Change>(payload) {
+                Ok(change) => change,
+                Err(e) => {
+                    warn!(error = %e, "unexpected change notification");
+                    return false;
+                }
+            };
+            debug!(table = %change.table, op = %change.op, "applying database change");
+            if !snapshot.apply(&change) {
+                return false;
+            }
+            words_changed |= change.table == "words";
+        }
+    }
+
+    if words_changed {
+        let (words, case_sensitive) = {
+            let snapshot = snapshot.read().unwrap();
+            let words: Vec<ContentRule> = snapshot.words.values().cloned().collect();
+            (words, snapshot.case_sensitive)
+        };
+        let content = compile_content(&words, case_sensitive);
+        snapshot.write().unwrap().content = content;
+    }
+    true
+}
+
+fn compile_content<'a>(
+    words: impl IntoIterator<Item = &'a ContentRule>,
+    case_sensitive: bool,
+) -> Result<ContentMatcher, String> {
+    ContentMatcher::new(words, case_sensitive).map_err(|e| {
+        error!(error = %e, "invalid content rule in the words table");
+        e.to_string()
+    })
+}
+
+/// The rows of one table by id, plus how often each value occurs so lookups don't have to scan the rows
+struct Rows<T> {
+    by_id: HashMap<i32, T>,
+    counts: HashMap<T, usize>,
+}
+
+impl<T> Default for Rows<T> {
+    fn default() -> Self {
+        Rows {
+            by_id: HashMap::new(),
+            counts: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone + Eq + Hash> Rows<T> {
+    fn insert(&mut self, id: i32, value: T) {
+        self.remove(id);
+        *self.counts.entry(value.clone()).or_default() += 1;
+        self.by_id.insert(id, value);
+    }
+
+    fn remove(&mut self, id: i32) {
+        if let Some(value) = self.by_id.remove(&id) {
+            if let Some(count) = self.counts.get_mut(&value) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&value);
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.by_id.clear();
+        self.counts.clear();
+    }
+
+    fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: std::borrow::Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.counts.contains_key(value)
+    }
+
+    fn values(&self) -> impl Iterator<Item = &T> {
+        self.counts.keys()
+    }
+
+    fn len(&self) -> usize {
+        self.by_id.len()
+    }
+}
+
+/// A change notification as sent by the `chief_notify_change` trigger function
+#[derive(Deserialize)]
+struct Change {
+    table: String,
+    op: String,
+    old: Option<Value>,
+    new: Option<Value>,
+}
+
+fn apply_change<T: Clone + Eq + Hash>(
+    rows: &mut Rows<T>,
+    change: &Change,
+    parse: impl Fn(&Value) -> Option<T>,
+) -> bool {
+    let id = |row: &Option<Value>| {
+        row.as_ref()
+            .and_then(|row| row.get("id"))
+            .and_then(Value::as_i64)
+            .and_then(|id| i32::try_from(id).ok())
+    };
+    let value = |row: &Option<Value>| row.as_ref().and_then(&parse);
+
+    match change.op.as_str() {
+        "INSERT" | "UPDATE" => {
+            if let Some(old_id) = id(&change.old) {
+                rows.remove(old_id);
+            }
+            match (id(&change.new), value(&change.new)) {
+                (Some(id), Some(value)) => rows.insert(id, value),
+                _ => return false,
+            }
+        }
+        "DELETE" => match id(&change.old) {
+            Some(id) => rows.remove(id),
+            None => return false,
+        },
+        "TRUNCATE" => rows.clear(),
+        _ => return false,
+    }
+    true
+}
+
+/// A dedicated connection that listens for change notifications
+struct Listener {
+    client: Client,
+    notifications: UnboundedReceiver<String>,
+}
+
+impl Listener {
+    async fn connect(
+        config: &DatabaseDatasourceConfig,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let (client, mut connection) = postgres_config(config)?
+            .connect(tls_connector(config)?)
+            .await?;
+
+        // Drive the connection and forward the notifications it receives. The channel closes when the connection does.
+        let (tx, notifications) = unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(message) = poll_fn(|cx| connection.poll_message(cx)).await {
+                match message {
+                    Ok(AsyncMessage::Notification(notification)) => {
+                        if tx.send(notification.payload().to_owned()).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!(error = %e, "database listener connection error");
+                        break;
+                    }
+                }
+            }
+        });
+
+        client.batch_execute(&format!("LISTEN {}", CHANNEL)).await?;
+        Ok(Listener {
+            client,
+            notifications,
+        })
+    }
+
+    /// Applies notifications to the snapshot until the connection breaks, returns why it broke
+    async fn run(
+        &mut self,
+        snapshot: &RwLock<Snapshot>,
+        resync_interval: Duration,
+    ) -> Box<dyn Error + Send + Sync> {
+        let mut resync = ResyncTimer::new(resync_interval);
+
+        loop {
+            let full_reload = tokio::select! {
+                payload = self.notifications.recv() => {
+                    let Some(payload) = payload else {
+                        return "connection closed".into();
+                    };
+                    // Apply everything that is already queued at once
+                    let mut payloads = vec![payload];
+                    while let Ok(payload) = self.notifications.try_recv() {
+                        payloads.push(payload);
+                    }
+                    !apply_changes(snapshot, &payloads)
+                }
+                _ = resync.tick() => true,
+            };
+
+            if full_reload {
+                let case_sensitive = snapshot.read().unwrap().case_sensitive;
+                match Snapshot::load(&self.client, case_sensitive).await {
+                    Ok(fresh) => *snapshot.write().unwrap() = fresh,
+                    Err(e) => return e,
+                }
+            }
+        }
+    }
+}
+
+/// Ticks every `cache_resync_interval`, or never when it is 0
+struct ResyncTimer(Option<Interval>);
+
+impl ResyncTimer {
+    fn new(interval: Duration) -> Self {
+        if interval.is_zero() {
+            return ResyncTimer(None);
+        }
+        // The first tick is one interval away, the snapshot was just loaded
+        ResyncTimer(Some(tokio::time::interval_at(
+            tokio::time::Instant::now() + interval,
+            interval,
+        )))
+    }
+
+    async fn tick(&mut self) {
+        match &mut self.0 {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(payload: &str) -> Change {
+        serde_json::from_str(payload).unwrap()
+    }
+
+    #[test]
+    fn test_apply_changes() {
+        let snapshot = RwLock::new(Snapshot::new(false));
+        let apply = |payloads: &[&str]| {
+            let payloads: Vec<String> = payloads.iter().map(|p| p.to_string()).collect();
+            apply_changes(&snapshot, &payloads)
+        };
+        let blocks = |content: &str| {
+            snapshot
+                .read()
+                .unwrap()
+                .content
+                .as_ref()
+                .unwrap()
+                .is_match(content)
+        };
+
+        // A batch, like the notifications of a bulk insert
+        assert!(apply(&[
+            r#"{"table":"words","op":"INSERT","old":null,"new":{"id":1,"word":"ETF"}}"#,
+            r#"{"table":"words","op":"INSERT","old":null,"new":{"id":2,"word":"ETF","pattern_type":"substring"}}"#,
+        ]));
+        assert!(blocks("my etf"));
+
+        // A duplicate row keeps the word blocked until both are gone
+        assert!(apply(&[
+            r#"{"table":"words","op":"DELETE","old":{"id":1,"word":"ETF"},"new":null}"#
+        ]));
+        assert!(blocks("my etf"));
+        assert!(apply(&[
+            r#"{"table":"words","op":"UPDATE","old":{"id":2,"word":"ETF"},"new":{"id":2,"word":"nft","pattern_type":"word"}}"#
+        ]));
+        assert!(!blocks("my etf"));
+        assert!(blocks("my NFT"));
+        assert!(!blocks("my NFTs"));
+
+        // An invalid regex fails the content checks instead of silently letting everything through
+        assert!(apply(&[
+            r#"{"table":"words","op":"INSERT","old":null,"new":{"id":3,"word":"(","pattern_type":"regex"}}"#
+        ]));
+        assert!(snapshot.read().unwrap().content.is_err());
+        assert!(apply(&[
+            r#"{"table":"words","op":"DELETE","old":{"id":3,"word":"(","pattern_type":"regex"},"new":null}"#
+        ]));
+        assert!(snapshot.read().unwrap().content.is_ok());
+
+        // A notification that can't be applied asks for a full reload, whatever else is in the batch
+        assert!(!apply(&[
+            r#"{"table":"words","op":"INSERT","old":null,"new":{"id":4,"word":"gm"}}"#,
+            r#"{"table":"words","op":"RESYNC"}"#,
+        ]));
+
+        let mut snapshot = snapshot.into_inner().unwrap();
+        assert!(snapshot.apply(&change(
+            r#"{"table":"kinds","op":"INSERT","old":null,"new":{"id":1,"kind":7}}"#
+        )));
+        assert!(snapshot.kinds.contains(&7));
+        assert!(snapshot.apply(&change(r#"{"table":"kinds","op":"TRUNCATE"}"#)));
+        assert!(!snapshot.kinds.contains(&7));
+
+        assert!(snapshot.apply(&change(
+            r#"{"table":"ip_ranges","op":"INSERT","old":null,"new":{"id":1,"ip_range":"203.0.113.0/24"}}"#
+        )));
+        assert!(snapshot
+            .ip_ranges
+            .values()
+            .any(|range| range.contains("203.0.113.7".parse().unwrap())));
+    }
+
+    #[tokio::test]
+    async fn test_resync_timer() {
+        let mut never = ResyncTimer::new(Duration::ZERO);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), never.tick())
+                .await
+                .is_err()
+        );
+
+        let mut timer = ResyncTimer::new(Duration::from_millis(20));
+        let started = tokio::time::Instant::now();
+        timer.tick().await;
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_resync_request() {
+        let mut snapshot = Snapshot::new(false);
+
+        assert!(!snapshot.apply(&change(r#"{"table":"words","op":"RESYNC"}"#)));
+    }
+}
@@ -1,43 +1,677 @@
+use crate::engine::config::{
+    KindRange, RateLimitAlgorithmConfig, RateLimitConfig, RateLimitKeyConfig,
+};
+use crate::engine::metrics::METRICS;
+use lru::LruCache;
 use nostr_sdk::Event;
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv6Addr};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
+use tracing::debug;
 
+/// Counts events per public key and/or source IP address and blocks those that post more than the configured rate.
+///
+/// Every event is counted against the first rule matching its kind, so a public key or address has a separate counter
+/// for every rule it posts under. The counters are kept in an LRU cache: once `max_entries` counters are tracked, the one that
+/// was used least recently is dropped to make room. Expired counters are removed by [`RateLimit::spawn_sweeper`].
 pub struct RateLimit {
-    pub cache: Arc<Mutex<HashMap<String, (Instant, u32)>>>,
+    pub cache: Mutex<LruCache<(usize, String), Counter>>,
+    pub rules: Vec<Rule>,
+    pub key: Key,
+    /// IPv6 addresses are counted per network of this prefix length, since a single client usually controls a whole
+    /// /64 or more
+    pub ipv6_prefix: u8,
+}
+
+/// What events are counted by
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Key {
+    Pubkey,
+    /// The address the event was submitted from. Events that didn't come from a client, e.g. imported or synced
+    /// ones, aren't limited.
+    Ip,
+    /// Both, an event is blocked when either its public key or its address exceeds the limit
+    PubkeyAndIp,
+}
+
+/// The limits for events of some kinds
+pub struct Rule {
+    /// Identifies the rule in saved state, so counters survive a restart as long as the rule exists
+    pub name: String,
+    /// The kinds the rule applies to, an empty list matches every kind
+    pub kinds: Vec<KindRange>,
+    pub limits: Limits,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Limits {
+    pub algorithm: Algorithm,
+    /// 0 means events of this rule aren't limited
     pub max_events: u32,
     pub time_window: Duration,
 }
 
+/// How the events of a public key are counted
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Algorithm {
+    /// At most `max_events` per time window. A window starts with the first event after the previous one expired,
+    /// so up to twice as many events can get through around the end of a window.
+    Fixed,
+    /// At most `max_events` within any `time_window`, remembers the time of every event in the window
+    SlidingLog,
+    /// Bursts of up to `burst` events, refilled with `refill_rate` events per second. Both default to `max_events`
+    /// per `time_window`.
+    TokenBucket {
+        burst: Option<u32>,
+        refill_rate: Option<f64>,
+    },
+}
+
+/// The rate limiting state of a single public key
+pub enum Counter {
+    Fixed { window_start: Instant, count: u32 },
+    SlidingLog(VecDeque<Instant>),
+    TokenBucket { tokens: f64, updated_at: Instant },
+}
+
+/// The counter of a single public key or address and rule in a form that survives a restart
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct RateLimitEntry {
+    /// The public key, or the address prefixed with "ip:"
+    #[serde(alias = "pubkey")]
+    pub key: String,
+    pub rule: String,
+    #[serde(flatten)]
+    pub counter: SavedCounter,
+}
+
+/// A [`Counter`] with all points in time in milliseconds since the Unix epoch
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub enum SavedCounter {
+    Fixed { window_start: u64, count: u32 },
+    SlidingLog { events: Vec<u64> },
+    TokenBucket { tokens: f64, updated_at: u64 },
+}
+
 impl RateLimit {
-    pub fn new(max_events: u32, time_window: Duration) -> Self {
+    /// Creates a rate limiter tracking at most `max_entries` counters, 0 means no limit
+    pub fn new(rules: Vec<Rule>, max_entries: usize) -> Self {
+        let cache = match NonZeroUsize::new(max_entries) {
+            Some(max_entries) => LruCache::new(max_entries),
+            None => LruCache::unbounded(),
+        };
         RateLimit {
-            cache: Arc::new(Mutex::new(HashMap::new())),
+            cache: Mutex::new(cache),
+            rules,
+            key: Key::Pubkey,
+            ipv6_prefix: 128,
+        }
+    }
+
+    /// Sets up the rules of the rate limit filter, with the top-level limits as the default for all other kinds
+    pub fn from_config(config: &RateLimitConfig) -> Self {
+        let limits = |algorithm, max_events, time_window: u32, burst, refill_rate| Limits {
+            algorithm: match algorithm {
+                RateLimitAlgorithmConfig::Fixed => Algorithm::Fixed,
+                RateLimitAlgorithmConfig::SlidingLog => Algorithm::SlidingLog,
+                RateLimitAlgorithmConfig::TokenBucket => {
+                    Algorithm::TokenBucket { burst, refill_rate }
+                }
+            },
             max_events,
-            time_window,
+            time_window: Duration::from_secs(time_window as u64),
+        };
+
+        let mut rules: Vec<Rule> = config
+            .rules
+            .iter()
+            .map(|rule| Rule {
+                name: rule
+                    .kinds
+                    .iter()
+                    .map(|kinds| kinds.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                kinds: rule.kinds.clone(),
+                limits: limits(
+                    rule.algorithm.unwrap_or(config.algorithm),
+                    rule.max_events,
+                    rule.time_window,
+                    rule.burst,
+                    rule.refill_rate,
+                ),
+            })
+            .collect();
+        rules.push(Rule {
+            name: String::from("default"),
+            kinds: Vec::new(),
+            limits: limits(
+                config.algorithm,
+                config.max_events,
+                config.time_window,
+                config.burst,
+                config.refill_rate,
+            ),
+        });
+
+        RateLimit {
+            key: match config.key {
+                RateLimitKeyConfig::Pubkey => Key::Pubkey,
+                RateLimitKeyConfig::Ip => Key::Ip,
+                RateLimitKeyConfig::PubkeyAndIp => Key::PubkeyAndIp,
+            },
+            ipv6_prefix: config.ipv6_prefix.min(128),
+            ..RateLimit::new(rules, config.max_entries)
         }
     }
 
-    pub async fn is_allowed(&self, event: &Event) -> bool {
-        let mut cache = self.cache.lock().await;
-        let pubkey = event.pubkey.to_string();
+    /// Counts the event and returns whether it is within the limits. `source_ip` is the address of the client that
+    /// submitted the event, if any.
+    pub async fn is_allowed(&self, event: &Event, source_ip: Option<IpAddr>) -> bool {
+        let kind = event.kind.as_u32();
         let now = Instant::now();
+        let mut allowed = true;
+
+        if matches!(self.key, Key::Pubkey | Key::PubkeyAndIp) {
+            allowed &= self.check(event.pubkey.to_string(), kind, now).await;
+        }
+        if let (Key::Ip | Key::PubkeyAndIp, Some(ip)) = (self.key, source_ip) {
+            allowed &= self.check(self.ip_key(ip), kind, now).await;
+        }
+
+        allowed
+    }
+
+    /// The counter key for an address, IPv6 addresses are reduced to their network
+    fn ip_key(&self, ip: IpAddr) -> String {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => format!("ip:{}", ip),
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.ipv6_prefix as u32)
+                    .unwrap_or(0);
+                let network = Ipv6Addr::from(u128::from(ip) & mask);
+                format!("ip:{}/{}", network, self.ipv6_prefix)
+            }
+        }
+    }
+
+    /// Counts an event of `key` at `now` and returns whether it is within the limit
+    async fn check(&self, key: String, kind: u32, now: Instant) -> bool {
+        let Some(rule) = self.rules.iter().position(|rule| {
+            rule.kinds.is_empty() || rule.kinds.iter().any(|kinds| kinds.contains(kind))
+        }) else {
+            return true;
+        };
+        let limits = &self.rules[rule].limits;
+        if limits.max_events == 0 {
+            return true;
+        }
+
+        let mut cache = self.cache.lock().await;
+        let key = (rule, key);
+        let allowed = match cache.get_mut(&key) {
+            Some(counter) => limits.consume(counter, now),
+            None => {
+                let mut counter = limits.new_counter(now);
+                let allowed = limits.consume(&mut counter, now);
+                if cache.push(key, counter).is_some() {
+                    METRICS.rate_limit_evictions.incr();
+                }
+                allowed
+            }
+        };
+
+        METRICS.rate_limit_entries.set(cache.len() as u64);
+        allowed
+    }
+
+    /// Removes the counters that have expired, they would be reset by the next event anyway
+    pub async fn sweep(&self) -> usize {
+        self.sweep_at(Instant::now()).await
+    }
+
+    async fn sweep_at(&self, now: Instant) -> usize {
+        let mut cache = self.cache.lock().await;
+
+        let expired: Vec<(usize, String)> = cache
+            .iter()
+            .filter(|((rule, _), counter)| self.rules[*rule].limits.is_expired(counter, now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            cache.pop(key);
+        }
+
+        METRICS.rate_limit_entries.set(cache.len() as u64);
+        expired.len()
+    }
+    /// Sweeps expired counters every `interval` for as long as the rate limiter is in use. An interval of zero
+    /// disables sweeping.
+    pub fn spawn_sweeper(self: &Arc<Self>, interval: Duration) {
+        if interval.is_zero() {
+            return;
+        }
+
+        // Hold a weak reference only, so the task ends once a reload replaced this rate limiter
+        let rate_limit: Weak<RateLimit> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(rate_limit) = rate_limit.upgrade() else {
+                    break;
+                };
+                let removed = rate_limit.sweep().await;
+                debug!(removed, "swept expired rate limit entries");
+            }
+        });
+    }
+
+    /// Returns all counters that haven't expired yet
+    pub async fn entries(&self) -> Vec<RateLimitEntry> {
+        let cache = self.cache.lock().await;
+        let clock = Clock::now();
+
+        cache
+            .iter()
+            .filter(|((rule, _), counter)| {
+                !self.rules[*rule].limits.is_expired(counter, clock.instant)
+            })
+            .map(|((rule, key), counter)| RateLimitEntry {
+                key: key.clone(),
+                rule: self.rules[*rule].name.clone(),
+                counter: match counter {
+                    Counter::Fixed {
+                        window_start,
+                        count,
+                    } => SavedCounter::Fixed {
+                        window_start: clock.to_unix_millis(*window_start),
+                        count: *count,
+                    },
+                    Counter::SlidingLog(events) => SavedCounter::SlidingLog {
+                        events: events.iter().map(|e| clock.to_unix_millis(*e)).collect(),
+                    },
+                    Counter::TokenBucket { tokens, updated_at } => SavedCounter::TokenBucket {
+                        tokens: *tokens,
+                        updated_at: clock.to_unix_millis(*updated_at),
+                    },
+                },
+            })
+            .collect()
+    }
+
+    /// Restores counters saved by [`RateLimit::entries`]. Counters that expired in the meantime, belong to a rule that
+    /// no longer exists or were saved with a different algorithm are skipped.
+    pub async fn restore(&self, entries: Vec<RateLimitEntry>) {
+        let mut cache = self.cache.lock().await;
+        let clock = Clock::now();
+
+        for entry in entries {
+            let Some(rule) = self.rules.iter().position(|rule| rule.name == entry.rule) else {
+                continue;
+            };
+            let limits = &self.rules[rule].limits;
+            let counter = match (entry.counter, limits.algorithm) {
+                (
+                    SavedCounter::Fixed {
+                        window_start,
+                        count,
+                    },
+                    Algorithm::Fixed,
+                ) => Counter::Fixed {
+                    window_start: clock.to_instant(window_start),
+                    count,
+                },
+                (SavedCounter::SlidingLog { events }, Algorithm::SlidingLog) => {
+                    Counter::SlidingLog(events.into_iter().map(|e| clock.to_instant(e)).collect())
+                }
+                (
+                    SavedCounter::TokenBucket { tokens, updated_at },
+                    Algorithm::TokenBucket { .. },
+                ) => Counter::TokenBucket {
+                    tokens,
+                    updated_at: clock.to_instant(updated_at),
+                },
+                _ => continue,
+            };
+            if !limits.is_expired(&counter, clock.instant) {
+                cache.push((rule, entry.key), counter);
+            }
+        }
+        METRICS.rate_limit_entries.set(cache.len() as u64);
+    }
+}
+
+impl Limits {
+    /// A counter for a public key that hasn't posted anything yet
+    fn new_counter(&self, now: Instant) -> Counter {
+        match self.algorithm {
+            Algorithm::Fixed => Counter::Fixed {
+                window_start: now,
+                count: 0,
+            },
+            Algorithm::SlidingLog => Counter::SlidingLog(VecDeque::new()),
+            Algorithm::TokenBucket { .. } => Counter::TokenBucket {
+                tokens: self.bucket().0,
+                updated_at: now,
+            },
+        }
+    }
+
+    /// Takes one event from the counter if the limit allows it
+    fn consume(&self, counter: &mut Counter, now: Instant) -> bool {
+        match counter {
+            Counter::Fixed {
+                window_start,
+                count,
+            } => {
+                if now - *window_start > self.time_window {
+                    *window_start = now;
+                    *count = 0;
+                }
+                if *count < self.max_events {
+                    *count += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+            Counter::SlidingLog(events) => {
+                while events
+                    .front()
+                    .is_some_and(|event| now - *event > self.time_window)
+                {
+                    events.pop_front();
+                }
+                if events.len() < self.max_events as usize {
+                    events.push_back(now);
+                    true
+                } else {
+                    false
+                }
+            }
+            Counter::TokenBucket { tokens, updated_at } => {
+                let (burst, refill_rate) = self.bucket();
+                *tokens = (*tokens + (now - *updated_at).as_secs_f64() * refill_rate).min(burst);
+                *updated_at = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Whether the counter is back to the state of a public key that hasn't posted anything, so it can be dropped
+    fn is_expired(&self, counter: &Counter, now: Instant) -> bool {
+        match counter {
+            Counter::Fixed { window_start, .. } => now - *window_start > self.time_window,
+            Counter::SlidingLog(events) => events
+                .back()
+                .is_none_or(|event| now - *event > self.time_window),
+            Counter::TokenBucket { tokens, updated_at } => {
+                let (burst, refill_rate) = self.bucket();
+                *tokens + (now - *updated_at).as_secs_f64() * refill_rate >= burst
+            }
+        }
+    }
+
+    /// Size and refill rate (per second) of the token bucket
+    fn bucket(&self) -> (f64, f64) {
+        let (burst, refill_rate) = match self.algorithm {
+            Algorithm::TokenBucket { burst, refill_rate } => (burst, refill_rate),
+            _ => (None, None),
+        };
+        (
+            burst.unwrap_or(self.max_events) as f64,
+            // The config only allows whole seconds, guard against dividing by a window of zero
+            refill_rate.unwrap_or(self.max_events as f64 / self.time_window.as_secs_f64().max(1.0)),
+        )
+    }
+}
+
+/// Translates between [`Instant`]s, which can't be stored, and wall clock time
+struct Clock {
+    instant: Instant,
+    wall: SystemTime,
+}
+
+impl Clock {
+    fn now() -> Self {
+        Clock {
+            instant: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    fn to_unix_millis(&self, instant: Instant) -> u64 {
+        (self.wall - (self.instant - instant))
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+
+    /// Points in time from before the process started are clamped to the earliest instant we can represent
+    fn to_instant(&self, unix_millis: u64) -> Instant {
+        // A point in the future means the clock went backwards, treat it as now
+        let age = self
+            .wall
+            .duration_since(UNIX_EPOCH + Duration::from_millis(unix_millis))
+            .unwrap_or_default();
+        self.instant.checked_sub(age).unwrap_or(self.instant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::{EventBuilder, Keys};
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    /// A rate limiter with a single rule for all kinds
+    fn rate_limit(
+        algorithm: Algorithm,
+        max_events: u32,
+        time_window: Duration,
+        max_entries: usize,
+    ) -> RateLimit {
+        let rule = Rule {
+            name: String::from("default"),
+            kinds: Vec::new(),
+            limits: Limits {
+                algorithm,
+                max_events,
+                time_window,
+            },
+        };
+        RateLimit::new(vec![rule], max_entries)
+    }
+
+    #[tokio::test]
+    async fn test_restore_entries() {
+        let rate_limit = rate_limit(Algorithm::Fixed, 2, MINUTE, 0);
+        let now = Clock::now().to_unix_millis(Instant::now());
+        rate_limit
+            .restore(vec![
+                RateLimitEntry {
+                    key: String::from("active"),
+                    rule: String::from("default"),
+                    counter: SavedCounter::Fixed {
+                        window_start: now - 1_000,
+                        count: 2,
+                    },
+                },
+                RateLimitEntry {
+                    key: String::from("expired"),
+                    rule: String::from("default"),
+                    counter: SavedCounter::Fixed {
+                        window_start: now - 120_000,
+                        count: 2,
+                    },
+                },
+                RateLimitEntry {
+                    key: String::from("other algorithm"),
+                    rule: String::from("default"),
+                    counter: SavedCounter::SlidingLog {
+                        events: vec![now - 1_000],
+                    },
+                },
+            ])
+            .await;
 
-        if let Some((timestamp, count)) = cache.get_mut(&pubkey) {
-            if now - *timestamp > self.time_window {
-                *timestamp = now;
-                *count = 1;
-                true
-            } else if *count < self.max_events {
-                *count += 1;
-                true
-            } else {
-                false
-            }
-        } else {
-            cache.insert(pubkey, (now, 1));
-            true
+        let entries = rate_limit.entries().await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, "active");
+        match entries[0].counter {
+            SavedCounter::Fixed {
+                window_start,
+                count,
+            } => {
+                assert_eq!(count, 2);
+                // Converting between clocks may be off by a millisecond or so
+                assert!(window_start.abs_diff(now - 1_000) < 50);
+            }
+            _ => panic!("unexpected counter {:?}", entries[0].counter),
         }
     }
+
+    fn event(keys: &Keys) -> Event {
+        EventBuilder::text_note("hello", []).to_event(keys).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() {
+        let rate_limit = rate_limit(Algorithm::Fixed, 1, MINUTE, 2);
+        let (first, second, third) = (Keys::generate(), Keys::generate(), Keys::generate());
+
+        assert!(rate_limit.is_allowed(&event(&first), None).await);
+        assert!(rate_limit.is_allowed(&event(&second), None).await);
+        // The first key is blocked, which also makes it the most recently used one
+        assert!(!rate_limit.is_allowed(&event(&first), None).await);
+        assert!(rate_limit.is_allowed(&event(&third), None).await);
+
+        // The second key was evicted to make room for the third and starts over
+        assert_eq!(rate_limit.cache.lock().await.len(), 2);
+        assert!(rate_limit.is_allowed(&event(&second), None).await);
+        assert!(!rate_limit.is_allowed(&event(&third), None).await);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_removes_expired_entries() {
+        let rate_limit = rate_limit(Algorithm::Fixed, 1, MINUTE, 0);
+        let start = Instant::now();
+        assert!(rate_limit.check(String::from("pubkey"), 1, start).await);
+
+        assert_eq!(rate_limit.sweep_at(start + MINUTE / 2).await, 0);
+        assert_eq!(rate_limit.sweep_at(start + MINUTE * 2).await, 1);
+        assert_eq!(rate_limit.cache.lock().await.len(), 0);
+    }
+
+    /// Sends one event at each offset (in seconds) and returns which ones got through
+    async fn allowed_at(rate_limit: &RateLimit, offsets: &[u64]) -> Vec<bool> {
+        let start = Instant::now();
+        let mut allowed = Vec::new();
+        for offset in offsets {
+            let now = start + Duration::from_secs(*offset);
+            allowed.push(rate_limit.check(String::from("pubkey"), 1, now).await);
+        }
+        allowed
+    }
+
+    #[tokio::test]
+    async fn test_fixed_window_allows_bursts_across_windows() {
+        let rate_limit = rate_limit(Algorithm::Fixed, 2, MINUTE, 0);
+        assert_eq!(
+            allowed_at(&rate_limit, &[0, 59, 59, 61, 61, 61]).await,
+            [true, true, false, true, true, false]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sliding_log_limits_any_window() {
+        let rate_limit = rate_limit(Algorithm::SlidingLog, 2, MINUTE, 0);
+        assert_eq!(
+            allowed_at(&rate_limit, &[0, 59, 59, 61, 61, 120]).await,
+            [true, true, false, true, false, true]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket_allows_burst_then_refill_rate() {
+        let algorithm = Algorithm::TokenBucket {
+            burst: Some(3),
+            refill_rate: Some(0.5),
+        };
+        let rate_limit = rate_limit(algorithm, 10, MINUTE, 0);
+        assert_eq!(
+            allowed_at(&rate_limit, &[0, 0, 0, 0, 1, 2, 3, 4]).await,
+            [true, true, true, false, false, true, false, true]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rules_count_separately_per_kind() {
+        let toml = r#"
+            enabled = true
+            max_events = 1
+            time_window = 60
+
+            [[rules]]
+            kinds = [7, "20000-29999"]
+            max_events = 2
+            time_window = 60
+        "#;
+        let rate_limit = RateLimit::from_config(&toml::from_str(toml).unwrap());
+        let now = Instant::now();
+        let check = |kind| rate_limit.check(String::from("pubkey"), kind, now);
+
+        assert!(check(7).await);
+        assert!(check(20001).await);
+        // Reactions and ephemeral events share the rule and its counter
+        assert!(!check(7).await);
+        // Other kinds fall back to the default limits with a counter of their own
+        assert!(check(1).await);
+        assert!(!check(0).await);
+        assert_eq!(rate_limit.cache.lock().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_ip_key_applies_to_client_events_only() {
+        let rate_limit = RateLimit {
+            key: Key::Ip,
+            ..rate_limit(Algorithm::Fixed, 1, MINUTE, 0)
+        };
+        let ip = "203.0.113.7".parse().ok();
+
+        assert!(rate_limit.is_allowed(&event(&Keys::generate()), ip).await);
+        // A fresh public key doesn't help once the address is over its limit
+        assert!(!rate_limit.is_allowed(&event(&Keys::generate()), ip).await);
+        // Events without a client address, e.g. from a sync, aren't limited
+        assert!(rate_limit.is_allowed(&event(&Keys::generate()), None).await);
+    }
+
+    #[test]
+    fn test_ipv6_prefix_aggregation() {
+        let rate_limit = RateLimit {
+            ipv6_prefix: 48,
+            ..rate_limit(Algorithm::Fixed, 1, MINUTE, 0)
+        };
+        let key = |ip: &str| rate_limit.ip_key(ip.parse().unwrap());
+
+        assert_eq!(key("2001:db8:1:2::1"), "ip:2001:db8:1::/48");
+        assert_eq!(key("2001:db8:1:ffff::1"), key("2001:db8:1:2::1"));
+        assert_ne!(key("2001:db8:2::1"), key("2001:db8:1::1"));
+        // IPv4 addresses and IPv4-mapped IPv6 addresses are never aggregated
+        assert_eq!(key("::ffff:203.0.113.7"), "ip:203.0.113.7");
+    }
 }
@@ -0,0 +1,348 @@
+use crate::engine::config::FilterModeConfig;
+use crate::engine::content::{ContentMatcher, ContentRule};
+use crate::engine::ip_range::IpRange;
+use crate::engine::validation::{ValidationDataSource, ValidationFuture};
+use rusqlite::{Connection, OptionalExtension, ToSql};
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Same tables as contrib/db/0002_initialize_db.sql, created on first run
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS public_keys
+(
+    id        INTEGER PRIMARY KEY,
+    publickey TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS kinds
+(
+    id   INTEGER PRIMARY KEY,
+    kind INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS words
+(
+    id           INTEGER PRIMARY KEY,
+    word         TEXT NOT NULL,
+    pattern_type TEXT NOT NULL DEFAULT 'substring' CHECK (pattern_type IN ('substring', 'word', 'regex')),
+    normalize    TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS ip_ranges
+(
+    id       INTEGER PRIMARY KEY,
+    ip_range TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_publickeys_publickey ON public_keys(publickey);
+CREATE INDEX IF NOT EXISTS idx_words_word ON words(word);
+CREATE INDEX IF NOT EXISTS idx_kinds_kind ON kinds(kind);
+";
+
+/// Columns added to the words table after its first release, they are added to databases created by older versions
+const WORDS_COLUMNS: &[(&str, &str)] = &[
+    (
+        "pattern_type",
+        "ALTER TABLE words ADD COLUMN pattern_type TEXT NOT NULL DEFAULT 'substring'
+            CHECK (pattern_type IN ('substring', 'word', 'regex'))",
+    ),
+    (
+        "normalize",
+        "ALTER TABLE words ADD COLUMN normalize TEXT NOT NULL DEFAULT ''",
+    ),
+];
+
+/// How long a query waits for a lock held by someone else, e.g. an admin editing the lists with `sqlite3`
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A SQLite database file as the datasource
+pub struct SqliteDataSource {
+    connection: Arc<Mutex<Connection>>,
+    content: Arc<Cached<ContentMatcher>>,
+    ip_ranges: Arc<Cached<Vec<IpRange>>>,
+    case_sensitive: bool,
+}
+
+/// A value loaded from the database, along with the `data_version` of the database it was loaded at
+type Cached<T> = Mutex<Option<(i64, Arc<T>)>>;
+
+impl SqliteDataSource {
+    /// Opens (or creates) the database file and creates the tables if they don't exist yet. `case_sensitive` decides
+    /// how content rules are matched.
+    pub fn open(
+        file_path: &str,
+        case_sensitive: bool,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let connection = Connection::open(file_path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        // WAL lets readers and a writer work at the same time, so the lists can be edited while chief is running
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+        for (column, add_column) in WORDS_COLUMNS {
+            let query = format!("SELECT {} FROM words LIMIT 0", column);
+            if connection.prepare(&query).is_err() {
+                connection.execute_batch(add_column)?;
+            }
+        }
+
+        Ok(SqliteDataSource {
+            connection: Arc::new(Mutex::new(connection)),
+            content: Arc::new(Mutex::new(None)),
+            ip_ranges: Arc::new(Mutex::new(None)),
+            case_sensitive,
+        })
+    }
+
+    /// Runs a query on the blocking thread pool and returns whether it found a row
+    fn exists<P>(&self, query: &'static str, params: P) -> ValidationFuture<'_>
+    where
+        P: IntoIterator + Send + 'static,
+        P::Item: ToSql,
+    {
+        let connection = self.connection.clone();
+        Box::pin(async move {
+            let found = tokio::task::spawn_blocking(move || {
+                let connection = connection.lock().unwrap();
+                let mut stmt = connection.prepare_cached(query)?;
+                stmt.query_row(rusqlite::params_from_iter(params), |_| Ok(()))
+                    .optional()
+                    .map(|row| row.is_some())
+            })
+            .await??;
+            Ok(found)
+        })
+    }
+}
+
+impl ValidationDataSource for SqliteDataSource {
+    fn is_pubkey_allowed(
+        &self,
+        pubkey: &str,
+        filter_mode: FilterModeConfig,
+    ) -> ValidationFuture<'_> {
+        let found = self.exists(
+            "SELECT 1 FROM public_keys WHERE publickey = ?1 LIMIT 1",
+            [pubkey.to_owned()],
+        );
+        Box::pin(async move { Ok(filter_mode.is_allowed(found.await?)) })
+    }
+
+    fn is_kind_allowed(&self, kind: u32, filter_mode: FilterModeConfig) -> ValidationFuture<'_> {
+        let found = self.exists("SELECT 1 FROM kinds WHERE kind = ?1 LIMIT 1", [kind]);
+        Box::pin(async move { Ok(filter_mode.is_allowed(found.await?)) })
+    }
+
+    fn is_content_allowed(&self, content: &str) -> ValidationFuture<'_> {
+        // The rules are compiled once and only reloaded after someone changed the database. Content filtering is always
+        // a blacklist.
+        let connection = self.connection.clone();
+        let case_sensitive = self.case_sensitive;
+        let cached = self.content.clone();
+        let content = content.to_owned();
+        Box::pin(async move {
+            let found = tokio::task::spawn_blocking(move || {
+                let connection = connection.lock().unwrap();
+                let matcher = load_cached(&connection, &cached, |connection| {
+                    Ok(ContentMatcher::new(
+                        &load_rules(connection)?,
+                        case_sensitive,
+                    )?)
+                })?;
+                Ok::<_, Box<dyn Error + Send + Sync>>(matcher.is_match(&content))
+            })
+            .await??;
+            Ok(!found)
+        })
+    }
+
+    fn is_ip_allowed(&self, ip: IpAddr, filter_mode: FilterModeConfig) -> ValidationFuture<'_> {
+        // SQLite has no network type, so the ranges are parsed once and matched here, like the content rules they are
+        // only reloaded after someone changed the database
+        let connection = self.connection.clone();
+        let cached = self.ip_ranges.clone();
+        Box::pin(async move {
+            let ranges = tokio::task::spawn_blocking(move || {
+                let connection = connection.lock().unwrap();
+                load_cached(&connection, &cached, load_ip_ranges)
+            })
+            .await??;
+
+            let found = ranges.iter().any(|range| range.contains(ip));
+            Ok(filter_mode.is_allowed(found))
+        })
+    }
+}
+
+/// Returns the cached value, or loads it again if the database changed since it was loaded
+fn load_cached<T>(
+    connection: &Connection,
+    cached: &Cached<T>,
+    load: impl FnOnce(&Connection) -> Result<T, Box<dyn Error + Send + Sync>>,
+) -> Result<Arc<T>, Box<dyn Error + Send + Sync>> {
+    let version: i64 = connection.query_row("PRAGMA data_version", [], |row| row.get(0))?;
+    let mut cached = cached.lock().unwrap();
+    match &*cached {
+        Some((loaded, value)) if *loaded == version => Ok(value.clone()),
+        _ => {
+            let value = Arc::new(load(connection)?);
+            *cached = Some((version, value.clone()));
+            Ok(value)
+        }
+    }
+}
+
+fn load_ip_ranges(connection: &Connection) -> Result<Vec<IpRange>, Box<dyn Error + Send + Sync>> {
+    let mut stmt = connection.prepare_cached("SELECT ip_range FROM ip_ranges")?;
+    let mut rows = stmt.query([])?;
+    let mut ranges = Vec::new();
+    while let Some(row) = rows.next()? {
+        ranges.push(row.get::<_, String>(0)?.parse()?);
+    }
+    Ok(ranges)
+}
+
+fn load_rules(connection: &Connection) -> Result<Vec<ContentRule>, Box<dyn Error + Send + Sync>> {
+    let mut stmt = connection.prepare_cached("SELECT word, pattern_type, normalize FROM words")?;
+    let mut rows = stmt.query([])?;
+    let mut rules = Vec::new();
+    while let Some(row) = rows.next()? {
+        rules.push(ContentRule {
+            word: row.get(0)?,
+            pattern_type: row.get::<_, String>(1)?.parse()?,
+            normalize: row.get::<_, String>(2)?.parse()?,
+        });
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBKEY: &str = "d30effaa4af9d1522381866487bb0009203d687d44278dea3826be1ea64c46a8";
+
+    fn data_source() -> SqliteDataSource {
+        let data_source = SqliteDataSource::open(":memory:", false).unwrap();
+        data_source
+            .connection
+            .lock()
+            .unwrap()
+            .execute_batch(&format!(
+                "INSERT INTO public_keys(publickey) VALUES ('{}');
+                 INSERT INTO kinds(kind) VALUES (1);
+                 INSERT INTO words(word) VALUES ('etf');
+                 INSERT INTO words(word, pattern_type) VALUES ('nft', 'word'), ('^gm\\b', 'regex');
+                 INSERT INTO ip_ranges(ip_range) VALUES ('203.0.113.0/24'), ('2001:db8::1');",
+                PUBKEY
+            ))
+            .unwrap();
+        data_source
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_pubkey_filter() {
+        let data_source = data_source();
+
+        assert!(data_source
+            .is_pubkey_allowed(PUBKEY, FilterModeConfig::Whitelist)
+            .await
+            .unwrap());
+        assert!(!data_source
+            .is_pubkey_allowed(PUBKEY, FilterModeConfig::Blacklist)
+            .await
+            .unwrap());
+        assert!(!data_source
+            .is_pubkey_allowed("unknown", FilterModeConfig::Whitelist)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_kind_filter() {
+        let data_source = data_source();
+
+        assert!(!data_source
+            .is_kind_allowed(1, FilterModeConfig::Blacklist)
+            .await
+            .unwrap());
+        assert!(data_source
+            .is_kind_allowed(7, FilterModeConfig::Blacklist)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_content_filter() {
+        let data_source = data_source();
+
+        assert!(!data_source
+            .is_content_allowed("Buy my ETF now")
+            .await
+            .unwrap());
+        assert!(data_source.is_content_allowed("hello world").await.unwrap());
+        assert!(!data_source.is_content_allowed("an NFT!").await.unwrap());
+        assert!(data_source.is_content_allowed("NFTs").await.unwrap());
+        assert!(!data_source.is_content_allowed("GM frens").await.unwrap());
+        assert!(data_source.is_content_allowed("say gm").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_existing_database() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("chief-test-existing-{}.db", std::process::id()));
+        let admin = Connection::open(&path).unwrap();
+        admin
+            .execute_batch(
+                "CREATE TABLE words (id INTEGER PRIMARY KEY, word TEXT NOT NULL);
+                 INSERT INTO words(word) VALUES ('etf');",
+            )
+            .unwrap();
+
+        // The pattern_type and normalize columns are added to databases created by older versions
+        let data_source = SqliteDataSource::open(path.to_str().unwrap(), false).unwrap();
+        let netflix_allowed = data_source.is_content_allowed("netflix").await.unwrap();
+        let ip: IpAddr = "198.51.100.1".parse().unwrap();
+        let ip_allowed_before = data_source
+            .is_ip_allowed(ip, FilterModeConfig::Blacklist)
+            .await
+            .unwrap();
+
+        // Rules and ranges added by someone else are picked up with the next event
+        admin
+            .execute_batch(
+                "INSERT INTO words(word, pattern_type) VALUES ('hello', 'word');
+                 INSERT INTO ip_ranges(ip_range) VALUES ('198.51.100.0/24');",
+            )
+            .unwrap();
+        let hello_allowed = data_source.is_content_allowed("hello world").await.unwrap();
+        let ip_allowed_after = data_source
+            .is_ip_allowed(ip, FilterModeConfig::Blacklist)
+            .await
+            .unwrap();
+
+        drop(data_source);
+        drop(admin);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        assert!(!netflix_allowed);
+        assert!(!hello_allowed);
+        assert!(ip_allowed_before);
+        assert!(!ip_allowed_after);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_ip_filter() {
+        let data_source = data_source();
+        let allowed =
+            |ip: &str| data_source.is_ip_allowed(ip.parse().unwrap(), FilterModeConfig::Blacklist);
+
+        assert!(!allowed("203.0.113.7").await.unwrap());
+        assert!(!allowed("2001:db8::1").await.unwrap());
+        assert!(allowed("2001:db8::2").await.unwrap());
+        assert!(allowed("198.51.100.1").await.unwrap());
+    }
+}
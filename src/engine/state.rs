@@ -0,0 +1,91 @@
+use crate::engine::config::{load_config, Config, FiltersConfig, SourceType};
+use crate::engine::datasource::build_data_source;
+use crate::engine::ratelimit::RateLimit;
+use crate::engine::validation::ValidationDataSource;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Everything needed to validate an event. A reload builds a complete new state and swaps it in at once,
+/// so an event is always validated against a single, consistent config and datasource.
+pub struct PolicyState {
+    pub config: Config,
+    pub data_source: Box<dyn ValidationDataSource>,
+    pub rate_limit: Arc<RateLimit>,
+    /// The filters of `config` scoped to every source type, indexed by [`SourceType`]
+    scoped_filters: [FiltersConfig; SourceType::ALL.len()],
+}
+
+impl PolicyState {
+    /// Loads the config file and sets up the datasource it points to.
+    ///
+    /// The rate limiter counters of `previous` are kept when the rate limit settings didn't change.
+    pub async fn load(
+        config_path: &str,
+        previous: Option<&PolicyState>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let config = load_config(config_path)?;
+        Self::from_config(config, previous).await
+    }
+
+    /// Sets up the datasource for an already loaded config, see [`PolicyState::load`]
+    pub async fn from_config(
+        config: Config,
+        previous: Option<&PolicyState>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let data_source = build_data_source(&config).await?;
+        Ok(Self::new(config, data_source, previous))
+    }
+
+    /// Puts together a state from a config and the datasource built for it, see [`PolicyState::load`]
+    pub fn new(
+        config: Config,
+        data_source: Box<dyn ValidationDataSource>,
+        previous: Option<&PolicyState>,
+    ) -> Self {
+        let rate_limit = match previous {
+            Some(previous) if previous.config.filters.rate_limit == config.filters.rate_limit => {
+                previous.rate_limit.clone()
+            }
+            _ => {
+                let rate_limit = Arc::new(RateLimit::from_config(&config.filters.rate_limit));
+                rate_limit.spawn_sweeper(Duration::from_secs(
+                    config.filters.rate_limit.sweep_interval,
+                ));
+                rate_limit
+            }
+        };
+
+        let scoped_filters = SourceType::ALL.map(|source| config.filters.scoped(source));
+
+        PolicyState {
+            config,
+            scoped_filters,
+            data_source,
+            rate_limit,
+        }
+    }
+
+    /// The filters that apply to events from `source`
+    pub fn filters(&self, source: SourceType) -> &FiltersConfig {
+        &self.scoped_filters[source.index()]
+    }
+}
+
+/// The currently active [`PolicyState`], shared between the plugin loop and the reloader
+pub struct SharedState(RwLock<Arc<PolicyState>>);
+
+impl SharedState {
+    pub fn new(state: PolicyState) -> Self {
+        SharedState(RwLock::new(Arc::new(state)))
+    }
+
+    /// Returns the active state. Events keep using the state they started with even if a reload happens meanwhile.
+    pub fn current(&self) -> Arc<PolicyState> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, state: PolicyState) {
+        *self.0.write().unwrap() = Arc::new(state);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::engine::ratelimit::RateLimitEntry;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// In-memory state that is saved on shutdown and picked up again by the next start
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+pub struct SavedState {
+    #[serde(default)]
+    pub rate_limit: Vec<RateLimitEntry>,
+}
+
+/// Reads the state file. A missing file isn't an error, there is simply nothing to restore on the first start.
+pub fn load(path: &str) -> Result<Option<SavedState>, Box<dyn Error + Send + Sync>> {
+    match fs::read(path) {
+        Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes the state file. The state goes to a temporary file first, so a crash halfway through leaves the previous
+/// state file intact.
+pub fn save(path: &str, state: &SavedState) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let tmp_path = Path::new(path).with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_vec(state)?)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ratelimit::SavedCounter;
+    use std::env;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = env::temp_dir().join(format!("chief-state-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        assert_eq!(load(path).unwrap(), None);
+
+        let state = SavedState {
+            rate_limit: vec![RateLimitEntry {
+                key: String::from(
+                    "d30effaa4af9d1522381866487bb0009203d687d44278dea3826be1ea64c46a8",
+                ),
+                rule: String::from("7,20000-29999"),
+                counter: SavedCounter::TokenBucket {
+                    tokens: 2.5,
+                    updated_at: 1_700_000_000_000,
+                },
+            }],
+        };
+        save(path, &state).unwrap();
+        assert_eq!(load(path).unwrap(), Some(state));
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,40 +1,103 @@
 use crate::engine::config::{FilterModeConfig, FiltersConfig};
+use crate::engine::content::{ContentMatcher, ContentRule};
+use crate::engine::ip_range::IpRange;
 use crate::engine::ratelimit::RateLimit;
 use nostr_sdk::Event;
 use serde::Deserialize;
 use std::error::Error;
+use std::fmt::Formatter;
 use std::future::Future;
+use std::net::IpAddr;
 use std::pin::Pin;
-use tokio_postgres::Client;
 
 #[derive(Debug)]
 pub enum BlockedType {
     Pubkey,
     Kind,
-    Word,
+    /// Blocked by the content filter, because of the content or one of the fields it scans
+    Word(ContentField),
     RateLimit,
+    Ip,
+}
+
+/// A part of an event the content filter matches the content rules against
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ContentField {
+    Content,
+    /// The value of a tag, by tag name
+    Tag(String),
+    /// A field of the JSON object in the content, like the `about` of kind 0 metadata
+    Metadata(String),
+}
+
+impl std::fmt::Display for ContentField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContentField::Content => write!(f, "content"),
+            ContentField::Tag(name) => write!(f, "{} tag", name),
+            ContentField::Metadata(name) => write!(f, "metadata {}", name),
+        }
+    }
+}
+
+/// A datasource failure while running one of the filters
+#[derive(Debug)]
+pub struct ValidationError {
+    /// The filter that was being evaluated when the error occurred
+    pub filter: BlockedType,
+    pub source: Box<dyn Error + Send + Sync>,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} filter failed: {}", self.filter, self.source)
+    }
+}
+
+impl Error for ValidationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
 }
 
 #[derive(Deserialize)]
 pub struct JsonDataSource {
     pub pubkeys: Vec<String>,
     pub kinds: Vec<u32>,
-    pub words: Vec<String>,
+    /// Content rules, either plain strings matched as substrings or objects with a `pattern_type`
+    pub words: Vec<ContentRule>,
+    /// Addresses and CIDR ranges for the IP filter
+    #[serde(default)]
+    pub ips: Vec<IpRange>,
+    /// `words` compiled for matching
+    #[serde(skip)]
+    content: ContentMatcher,
 }
 
 impl JsonDataSource {
-    pub fn new_from_file(file_path: &str) -> Result<Self, Box<dyn Error>> {
+    pub fn new_from_file(
+        file_path: &str,
+        case_sensitive: bool,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
         let file = std::fs::File::open(file_path)?;
         let reader = std::io::BufReader::new(file);
-        let data: JsonDataSource = serde_json::from_reader(reader)?;
+        let mut data: JsonDataSource = serde_json::from_reader(reader)?;
+        data.content = ContentMatcher::new(&data.words, case_sensitive)?;
         Ok(data)
     }
 }
 
-type ValidationResult = Result<bool, Box<dyn Error>>;
-type ValidationFuture<'a> = Pin<Box<dyn Future<Output = ValidationResult> + Send + 'a>>;
+pub type ValidationResult = Result<bool, Box<dyn Error + Send + Sync>>;
+pub type ValidationFuture<'a> = Pin<Box<dyn Future<Output = ValidationResult> + Send + 'a>>;
 
-pub trait ValidationDataSource {
+pub type ContentValidationFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Option<usize>, Box<dyn Error + Send + Sync>>> + Send + 'a>>;
+
+pub type CombinedValidationFuture<'a> = Pin<
+    Box<dyn Future<Output = Result<Option<BlockedType>, Box<dyn Error + Send + Sync>>> + Send + 'a>,
+>;
+
+pub trait ValidationDataSource: Send + Sync {
     fn is_pubkey_allowed(
         &self,
         pubkey: &str,
@@ -42,100 +105,102 @@ pub trait ValidationDataSource {
     ) -> ValidationFuture<'_>;
     fn is_kind_allowed(&self, kind: u32, filter_mode: FilterModeConfig) -> ValidationFuture<'_>;
     fn is_content_allowed(&self, content: &str) -> ValidationFuture<'_>;
-}
+    fn is_ip_allowed(&self, ip: IpAddr, filter_mode: FilterModeConfig) -> ValidationFuture<'_>;
 
-impl ValidationDataSource for tokio_postgres::Client {
-    fn is_pubkey_allowed(&self, pubkey: &str, filter_mode: FilterModeConfig) -> ValidationFuture {
-        let pubkey = pubkey.to_owned();
+    /// Returns the index of the first of `contents` that matches a content rule. Datasources that have to fetch the
+    /// rules override this to fetch them once for all of them.
+    fn first_blocked_content<'a>(&'a self, contents: &'a [String]) -> ContentValidationFuture<'a> {
         Box::pin(async move {
-            let params: &[&(dyn tokio_postgres::types::ToSql + Sync)] = &[&pubkey];
-            is_allowed(
-                self,
-                "SELECT publickey FROM public_keys WHERE publickey = $1",
-                params,
-                filter_mode,
-            )
-            .await
-        })
-    }
-
-    fn is_kind_allowed(&self, kind: u32, filter_mode: FilterModeConfig) -> ValidationFuture {
-        Box::pin(async move {
-            // We have to cast the event kind u32 to i32 to make tokio_postgres happy
-            let i32_kind = kind as i32;
-
-            let params: &[&(dyn tokio_postgres::types::ToSql + Sync)] = &[&i32_kind];
-            is_allowed(
-                self,
-                "SELECT kind FROM kinds WHERE kind = $1",
-                params,
-                filter_mode,
-            )
-            .await
+            for (index, content) in contents.iter().enumerate() {
+                if !self.is_content_allowed(content).await? {
+                    return Ok(Some(index));
+                }
+            }
+            Ok(None)
         })
     }
 
-    fn is_content_allowed(&self, content: &str) -> ValidationFuture {
-        let content = content.to_owned();
-        Box::pin(async move {
-            let word_stmt = self
-                .prepare("SELECT word FROM words WHERE $1 ILIKE '%' || word || '%'")
-                .await?;
-
-            let rows = self.query(&word_stmt, &[&content]).await?;
-
-            // We don't use the generic is_allowed function here because we want to get the blacklisted words. We're also always
-            // using the blacklist filter mode for this check and therefore don't need the whitelist check functionality.
-            Ok(rows.is_empty())
-        })
+    /// Runs all enabled IP, public key, kind and content checks at once and returns the first filter that blocks the
+    /// event. Datasources that can't do better than running the checks one by one return `None`.
+    fn validate_combined<'a>(
+        &'a self,
+        _event: &'a Event,
+        _source_ip: Option<IpAddr>,
+        _filters: &'a FiltersConfig,
+    ) -> Option<CombinedValidationFuture<'a>> {
+        None
     }
 }
 
 impl ValidationDataSource for JsonDataSource {
-    fn is_pubkey_allowed(&self, pubkey: &str, filter_mode: FilterModeConfig) -> ValidationFuture {
-        let pubkey = pubkey.to_owned();
+    fn is_pubkey_allowed(
+        &self,
+        pubkey: &str,
+        filter_mode: FilterModeConfig,
+    ) -> ValidationFuture<'_> {
+        let found = self.pubkeys.iter().any(|listed| listed == pubkey);
+        Box::pin(async move { Ok(filter_mode.is_allowed(found)) })
+    }
 
-        Box::pin(async move {
-            match filter_mode {
-                FilterModeConfig::Blacklist => Ok(!self.pubkeys.contains(&pubkey.to_string())),
-                FilterModeConfig::Whitelist => Ok(self.pubkeys.contains(&pubkey.to_string())),
-            }
-        })
+    fn is_kind_allowed(&self, kind: u32, filter_mode: FilterModeConfig) -> ValidationFuture<'_> {
+        let found = self.kinds.contains(&kind);
+        Box::pin(async move { Ok(filter_mode.is_allowed(found)) })
     }
 
-    fn is_kind_allowed(&self, kind: u32, filter_mode: FilterModeConfig) -> ValidationFuture {
-        Box::pin(async move {
-            match filter_mode {
-                FilterModeConfig::Blacklist => Ok(!self.kinds.contains(&kind)),
-                FilterModeConfig::Whitelist => Ok(self.kinds.contains(&kind)),
-            }
-        })
+    fn is_content_allowed(&self, content: &str) -> ValidationFuture<'_> {
+        let found = self.content.is_match(content);
+        Box::pin(async move { Ok(!found) })
     }
 
-    fn is_content_allowed(&self, content: &str) -> ValidationFuture {
-        let content = content.to_owned();
-        Box::pin(async move {
-            let contains_blacklisted_word = self
-                .words
-                .iter()
-                .any(|word| content.contains(word.as_str()));
-            Ok(!contains_blacklisted_word)
-        })
+    fn is_ip_allowed(&self, ip: IpAddr, filter_mode: FilterModeConfig) -> ValidationFuture<'_> {
+        let found = self.ips.iter().any(|range| range.contains(ip));
+        Box::pin(async move { Ok(filter_mode.is_allowed(found)) })
     }
 }
 
-/// Validates the event data against a set of selected filter strategies. Could be public key, kind and/or content validation.
+/// Counts the event against the rate limit of its public key and/or source address and returns whether it exceeds the
+/// limit. Events have to be passed in the order they arrive, so the earliest events of a burst are the ones that get
+/// through.
+pub async fn is_rate_limited(
+    event: &Event,
+    source_ip: Option<IpAddr>,
+    filters: &FiltersConfig,
+    rate_limit: &RateLimit,
+) -> bool {
+    filters.rate_limit.enabled && !rate_limit.is_allowed(event, source_ip).await
+}
+
+/// Validates the event data against a set of selected filter strategies. Could be IP, public key, kind and/or content validation.
+///
+/// `source_ip` is the address of the client that submitted the event, events without one skip the IP filter.
 pub async fn validate_event(
     data_source: &dyn ValidationDataSource,
     event: &Event,
+    source_ip: Option<IpAddr>,
     filters: &FiltersConfig,
-    rate_limit: &RateLimit,
-) -> Result<Option<BlockedType>, Box<dyn Error>> {
-    if filters.rate_limit.enabled
-        && filters.rate_limit.max_events > 0
-        && !rate_limit.is_allowed(event).await
-    {
-        return Ok(Some(BlockedType::RateLimit));
+) -> Result<Option<BlockedType>, ValidationError> {
+    if let Some(combined) = data_source.validate_combined(event, source_ip, filters) {
+        // A failure can't be attributed to a single filter, blame the one that would have run first
+        let blocked = combined.await.map_err(|source| ValidationError {
+            filter: first_datasource_filter(event, source_ip, filters)
+                .unwrap_or(BlockedType::Word(ContentField::Content)),
+            source,
+        })?;
+        return Ok(blocked);
+    }
+
+    // Check if IP validation is activated and the event came from a client
+    if let (true, Some(ip)) = (filters.ip.enabled, source_ip) {
+        let ip_allowed = data_source
+            .is_ip_allowed(ip, filters.ip.filter_mode.to_owned())
+            .await
+            .map_err(|source| ValidationError {
+                filter: BlockedType::Ip,
+                source,
+            })?;
+        if !ip_allowed {
+            return Ok(Some(BlockedType::Ip));
+        }
     }
 
     // Check if public key validation is activated
@@ -145,7 +210,11 @@ pub async fn validate_event(
                 event.pubkey.to_string().as_str(),
                 filters.pubkey.filter_mode.to_owned(),
             )
-            .await?;
+            .await
+            .map_err(|source| ValidationError {
+                filter: BlockedType::Pubkey,
+                source,
+            })?;
         if !publickey_allowed {
             return Ok(Some(BlockedType::Pubkey));
         }
@@ -155,45 +224,99 @@ pub async fn validate_event(
     if filters.kind.enabled {
         let kind_allowed = data_source
             .is_kind_allowed(event.kind.as_u32(), filters.kind.filter_mode.to_owned())
-            .await?;
+            .await
+            .map_err(|source| ValidationError {
+                filter: BlockedType::Kind,
+                source,
+            })?;
         if !kind_allowed {
             return Ok(Some(BlockedType::Kind));
         }
     }
 
-    // Check if content validation is activated
-    if filters.content.enabled {
-        // Validate content only if we get a match with the event kind or the validated kinds list is empty
-        if filters
-            .content
-            .validated_kinds
-            .contains(&event.kind.as_u32())
-            || filters.content.validated_kinds.is_empty()
-        {
-            let content_allowed = data_source
-                .is_content_allowed(event.content.as_str())
-                .await?;
-            if !content_allowed {
-                return Ok(Some(BlockedType::Word));
+    // Check if content validation is activated, the content and the configured fields are matched at once
+    let fields = content_fields(event, filters);
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    let contents: Vec<String> = fields.iter().map(|(_, value)| value.clone()).collect();
+    let blocked = data_source
+        .first_blocked_content(&contents)
+        .await
+        .map_err(|source| ValidationError {
+            filter: BlockedType::Word(ContentField::Content),
+            source,
+        })?;
+    Ok(blocked.map(|index| BlockedType::Word(fields[index].0.clone())))
+}
+
+/// Everything the content filter scans for the event: the content followed by the tag values and metadata fields that
+/// are configured for its kind, in the order of the configuration. Only string metadata fields are scanned, content
+/// that isn't a JSON object has none. Empty when the content filter doesn't apply to the event.
+pub fn content_fields(event: &Event, filters: &FiltersConfig) -> Vec<(ContentField, String)> {
+    if !content_filter_applies(event, filters) {
+        return Vec::new();
+    }
+    let kind = event.kind.as_u32();
+    let mut metadata: Option<Option<serde_json::Map<String, serde_json::Value>>> = None;
+    let mut fields = vec![(ContentField::Content, event.content.clone())];
+    for config in &filters.content.fields {
+        if !config.kinds.iter().any(|range| range.contains(kind)) {
+            continue;
+        }
+        for name in &config.tags {
+            for tag in event.tags.iter() {
+                let tag = tag.as_vec();
+                if let (Some(tag_name), Some(value)) = (tag.first(), tag.get(1)) {
+                    if tag_name == name {
+                        fields.push((ContentField::Tag(name.clone()), value.clone()));
+                    }
+                }
+            }
+        }
+        if config.metadata.is_empty() {
+            continue;
+        }
+        let metadata = metadata.get_or_insert_with(|| serde_json::from_str(&event.content).ok());
+        for name in &config.metadata {
+            if let Some(value) = metadata
+                .as_ref()
+                .and_then(|metadata| metadata.get(name))
+                .and_then(|value| value.as_str())
+            {
+                fields.push((ContentField::Metadata(name.clone()), value.to_owned()));
             }
         }
     }
+    fields
+}
 
-    Ok(None)
+/// Whether the content filter is enabled and the event kind is one it validates
+pub fn content_filter_applies(event: &Event, filters: &FiltersConfig) -> bool {
+    // Validate content only if we get a match with the event kind or the validated kinds list is empty
+    filters.content.enabled
+        && (filters
+            .content
+            .validated_kinds
+            .contains(&event.kind.as_u32())
+            || filters.content.validated_kinds.is_empty())
 }
 
-/// Generic function to query database for something and decide whether to accept or deny the event based on filter mode selected
-async fn is_allowed(
-    client: &Client,
-    query: &str,
-    params: &[&(dyn tokio_postgres::types::ToSql + Sync)],
-    filter_mode: FilterModeConfig,
-) -> Result<bool, Box<dyn Error>> {
-    let stmt = client.prepare(query).await?;
-    let rows = client.query(&stmt, params).await?;
-
-    match filter_mode {
-        FilterModeConfig::Blacklist => Ok(rows.is_empty()),
-        FilterModeConfig::Whitelist => Ok(!rows.is_empty()),
+/// The first datasource backed filter that runs for the event, `None` when the event needs no datasource check
+pub fn first_datasource_filter(
+    event: &Event,
+    source_ip: Option<IpAddr>,
+    filters: &FiltersConfig,
+) -> Option<BlockedType> {
+    if filters.ip.enabled && source_ip.is_some() {
+        Some(BlockedType::Ip)
+    } else if filters.pubkey.enabled {
+        Some(BlockedType::Pubkey)
+    } else if filters.kind.enabled {
+        Some(BlockedType::Kind)
+    } else if content_filter_applies(event, filters) {
+        Some(BlockedType::Word(ContentField::Content))
+    } else {
+        None
     }
 }
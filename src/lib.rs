@@ -0,0 +1,4 @@
+//! The filters and datasources of chief. The strfry plugin itself lives in the `chief` binary, this library exists so
+//! that the benchmarks can get at the engine.
+
+pub mod engine;
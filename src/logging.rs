@@ -0,0 +1,38 @@
+use chief::engine::config::{LogFormatConfig, LoggingConfig};
+use std::error::Error;
+use std::path::Path;
+use tracing::Level;
+use tracing_appender::non_blocking::WorkerGuard;
+
+/// Installs the global logger. Logs never go to stdout because strfry reads plugin responses from there.
+///
+/// The returned guard flushes buffered log lines when dropped and must be kept alive until the process exits.
+pub fn init(config: &LoggingConfig) -> Result<WorkerGuard, Box<dyn Error>> {
+    let (writer, guard) = match &config.file_path {
+        Some(file_path) => {
+            let path = Path::new(file_path);
+            let directory = path.parent().unwrap_or(Path::new("."));
+            let file_name = path
+                .file_name()
+                .ok_or_else(|| format!("invalid log file path {}", file_path))?;
+            tracing_appender::non_blocking(tracing_appender::rolling::never(directory, file_name))
+        }
+        None => tracing_appender::non_blocking(std::io::stderr()),
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(Level::from(config.level))
+        .with_writer(writer)
+        .with_ansi(false);
+
+    match config.format {
+        LogFormatConfig::Text => builder.try_init().map_err(|e| e as Box<dyn Error>)?,
+        LogFormatConfig::Json => builder
+            .json()
+            .flatten_event(true)
+            .try_init()
+            .map_err(|e| e as Box<dyn Error>)?,
+    }
+
+    Ok(guard)
+}
@@ -1,180 +1,664 @@
-mod engine;
-
-use crate::engine::config::{load_config, DataSource};
-use crate::engine::ratelimit::RateLimit;
-use crate::engine::validation;
-use crate::engine::validation::{
-    validate_event, BlockedType, JsonDataSource, ValidationDataSource,
-};
-use nostr_sdk::Event;
-use serde::{Deserialize, Serialize};
+mod cli;
+mod logging;
+mod protocol;
+mod reload;
+
+use crate::cli::{Cli, Command};
+use crate::protocol::{decode_request, Request, Response};
+use chief::engine::config::{load_config, Action, Config};
+use chief::engine::datasource;
+use chief::engine::metrics;
+use chief::engine::metrics::METRICS;
+use chief::engine::migrations;
+use chief::engine::postgres;
+use chief::engine::state::{PolicyState, SharedState};
+use chief::engine::state_file;
+use chief::engine::state_file::SavedState;
+use chief::engine::validation::{is_rate_limited, validate_event, BlockedType, ContentField};
+use clap::Parser;
 use std::error::Error;
-use std::process;
+use std::io::ErrorKind;
+use std::process::ExitCode;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::io::{stdin, stdout, AsyncWriteExt};
+use tokio::io::{stdin, stdout, AsyncWriteExt, Stdout};
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio_postgres::{Error as PGError, NoTls};
-
-/// Represents a request from the relay
-#[derive(Deserialize)]
-struct Request {
-    #[serde(rename = "type")]
-    type_field: String,
-    event: Event,
-    #[serde(rename = "receivedAt")]
-    _received_at: u64,
-    #[serde(rename = "sourceType")]
-    _source_type: String,
-    #[serde(rename = "sourceInfo")]
-    source_info: String,
-}
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
 
-/// Represents the response we provide back to the relay
-#[derive(Serialize)]
-struct Response {
-    id: String,
-    action: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    msg: Option<String>,
-}
+/// The task writing responses to stdout, it only fails when stdout is closed
+type WriterHandle = JoinHandle<Result<(), Box<dyn Error + Send + Sync>>>;
+
+/// Exit status when stdin or stdout is broken and chief can't talk to strfry anymore
+const EXIT_IO_ERROR: u8 = 3;
+
+/// Exit status when events were still being validated at the end of the drain timeout and never got a response
+const EXIT_DRAIN_TIMEOUT: u8 = 4;
+
+/// Number of failed reads in a row after which we consider stdin broken and exit
+const MAX_CONSECUTIVE_READ_ERRORS: u32 = 10;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> Result<ExitCode, Box<dyn Error + Send + Sync>> {
+    let cli = Cli::parse();
+    let command = cli.command();
+
+    if command == Command::Version {
+        println!("chief {}", env!("CARGO_PKG_VERSION"));
+        return Ok(ExitCode::SUCCESS);
+    }
+
     // Load config
-    let config = match load_config("/etc/chief/config.toml") {
+    let config = match load_config(&cli.config) {
         Ok(cfg) => cfg,
         Err(e) => {
-            eprintln!("Failed to load config: {:?}", e);
-            process::exit(1);
+            eprintln!("Failed to load config from {}: {}", cli.config, e);
+            return Ok(ExitCode::FAILURE);
         }
     };
 
-    // Select either JSON file or DB datasource, configured in the config.toml file
-    let data_source = if config.datasource_mode == DataSource::Db {
-        // Set up a database as the datasource
-        let (client, connection) = tokio_postgres::connect(
-            format!(
-                "host={} port={} user={} password={} dbname={}",
-                config.database.host,
-                config.database.port,
-                config.database.user,
-                config.database.password,
-                config.database.dbname
-            )
-            .as_str(),
-            NoTls,
-        )
-        .await?;
+    // Keep the guard alive for the lifetime of the process so buffered log lines get flushed on exit. Failures return an
+    // exit code rather than calling `process::exit`, which would skip dropping it.
+    let _log_guard = match logging::init(&config.logging) {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("Failed to set up logging: {}", e);
+            return Ok(ExitCode::FAILURE);
+        }
+    };
 
-        // The connection object performs the actual communication with the database,
-        // so spawn it off to run on its own.
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                eprintln!("connection error: {}", e);
+    match command {
+        Command::CheckConfig => {
+            if let Err(e) = datasource::build_data_source(&config).await {
+                eprintln!("Failed to set up datasource: {}", e);
+                return Ok(ExitCode::FAILURE);
             }
-        });
+            println!("configuration file {} is valid", cli.config);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Migrate { check } => migrate(&config, check).await,
+        Command::Run | Command::Version => run(cli.config, config).await,
+    }
+}
 
-        Box::new(client) as Box<dyn validation::ValidationDataSource>
-    } else {
-        // Set up a JSON file as the datasource
-        let json_data_source = JsonDataSource::new_from_file(config.json.file_path.as_str())?;
-        Box::new(json_data_source) as Box<dyn ValidationDataSource>
-    };
+/// Applies or, with `check`, only reports pending schema migrations of the Postgres datasource. The check fails when
+/// there are any.
+async fn migrate(config: &Config, check: bool) -> Result<ExitCode, Box<dyn Error + Send + Sync>> {
+    let mut client = postgres::connect(&config.database).await?;
+
+    if check {
+        let pending = migrations::pending(&client).await?;
+        if pending.is_empty() {
+            println!("database schema is up to date");
+            return Ok(ExitCode::SUCCESS);
+        }
+        for migration in pending {
+            println!(
+                "pending migration {:04} {}",
+                migration.version, migration.name
+            );
+        }
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let applied = migrations::migrate(&mut client).await?;
+    for migration in &applied {
+        println!(
+            "applied migration {:04} {}",
+            migration.version, migration.name
+        );
+    }
+    if applied.is_empty() {
+        println!("database schema is up to date");
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs the strfry plugin loop, reading requests from stdin and writing responses to stdout until stdin is closed or
+/// chief receives SIGTERM or SIGINT. Events already read are validated and answered before it returns.
+async fn run(
+    config_path: String,
+    config: Config,
+) -> Result<ExitCode, Box<dyn Error + Send + Sync>> {
+    metrics::spawn_reporter(Duration::from_secs(config.metrics.log_interval));
+    let max_in_flight = config.processing.max_in_flight.max(1);
+    let drain_timeout = Duration::from_secs(config.processing.drain_timeout);
+    let state_path = config.state.file_path.clone();
 
-    let rate_limit_engine = RateLimit::new(
-        config.filters.rate_limit.max_events,
-        Duration::from_secs(config.filters.rate_limit.time_window as u64),
+    let state = PolicyState::from_config(config, None).await?;
+    info!(
+        version = env!("CARGO_PKG_VERSION"),
+        datasource = ?state.config.datasource_mode,
+        max_in_flight,
+        "chief started"
     );
 
-    // Set up stdin and stdout handles
-    let mut reader = BufReader::new(stdin()).lines();
-    let mut writer = stdout();
+    if let Some(path) = &state_path {
+        restore_state(path, &state).await;
+    }
+
+    let shared = Arc::new(SharedState::new(state));
+    reload::spawn_reloader(config_path, shared.clone())?;
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut exit_code = ExitCode::SUCCESS;
+
+    // Responses are written as soon as their event is validated, strfry matches them to events by id
+    let (responses, writer) = spawn_writer(max_in_flight);
+    let in_flight = Arc::new(Semaphore::new(max_in_flight));
+
+    let mut reader = BufReader::new(stdin());
+    let mut buf = Vec::new();
+    let mut consecutive_read_errors = 0;
+
+    loop {
+        buf.clear();
+        let read = tokio::select! {
+            read = reader.read_until(b'\n', &mut buf) => read,
+            _ = terminate.recv() => {
+                info!("received SIGTERM, shutting down");
+                break;
+            }
+            _ = interrupt.recv() => {
+                info!("received SIGINT, shutting down");
+                break;
+            }
+        };
+        match read {
+            // EOF, strfry closed our stdin
+            Ok(0) => {
+                info!("stdin closed, shutting down");
+                break;
+            }
+            Ok(_) => consecutive_read_errors = 0,
+            Err(e) => {
+                METRICS.read_errors.incr();
+                consecutive_read_errors += 1;
+                if consecutive_read_errors >= MAX_CONSECUTIVE_READ_ERRORS {
+                    error!(error = %e, "giving up reading from stdin");
+                    exit_code = ExitCode::from(EXIT_IO_ERROR);
+                    break;
+                }
+                warn!(error = %e, "failed to read from stdin");
+                continue;
+            }
+        }
+
+        // Invalid UTF-8 is replaced rather than rejected, the JSON decoder reports anything unusable
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        METRICS.requests.incr();
 
-    while let Some(line) = reader.next_line().await.unwrap() {
         // Deserialize request from strfry
-        let req: Request = serde_json::from_str(&line)?;
+        let req = match decode_request(line) {
+            Ok(req) => req,
+            Err(err) => {
+                METRICS.parse_errors.incr();
+                // Answer whenever we can, otherwise strfry is left waiting for a verdict on this event
+                match err.event_id {
+                    Some(event_id) => {
+                        warn!(event_id = %event_id, error = %err, "failed to parse request");
+                        let res = Response::reject(event_id.to_hex(), "error: invalid request");
+                        if responses.send(res).await.is_err() {
+                            break;
+                        }
+                    }
+                    None => warn!(error = %err, "failed to parse request, no event id to reply to"),
+                }
+                continue;
+            }
+        };
 
         // Type is currently always "new", anything else is an error as per Strfry documentation
         if req.type_field != "new" {
-            eprintln!("unexpected request type {}", req.type_field);
+            METRICS.parse_errors.incr();
+            warn!(event_id = %req.event.id, request_type = %req.type_field, "unexpected request type");
+            let res = Response::reject(req.event.id.to_hex(), "error: unexpected request type");
+            if responses.send(res).await.is_err() {
+                break;
+            }
             continue;
         }
 
-        // Build default response
-        let mut res = Response {
+        // Validate against a single snapshot of the config and datasource, even if a reload happens meanwhile
+        if !dispatch(req, shared.current(), &in_flight, &responses).await {
+            break;
+        }
+    }
+
+    // Stop reading and let the events still in flight finish, the writer ends once all of them have been answered
+    drop(responses);
+    match tokio::time::timeout(drain_timeout, writer).await {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(_))) => exit_code = ExitCode::from(EXIT_IO_ERROR),
+        Ok(Err(e)) => return Err(e.into()),
+        Err(_) => {
+            error!(
+                in_flight = max_in_flight - in_flight.available_permits(),
+                "events still in flight after the drain timeout, giving up on them"
+            );
+            exit_code = ExitCode::from(EXIT_DRAIN_TIMEOUT);
+        }
+    }
+
+    if let Some(path) = &state_path {
+        if let Err(e) = save_state(path, &shared.current()).await {
+            error!(path, error = %e, "failed to save state");
+            if exit_code == ExitCode::SUCCESS {
+                exit_code = ExitCode::FAILURE;
+            }
+        }
+    }
+
+    METRICS.log();
+    info!("chief stopped");
+
+    Ok(exit_code)
+}
+
+/// Loads the state saved by the previous run. Chief still starts with a clean slate if the file is unusable.
+async fn restore_state(path: &str, state: &PolicyState) {
+    match state_file::load(path) {
+        Ok(Some(saved)) => {
+            let entries = saved.rate_limit.len();
+            state.rate_limit.restore(saved.rate_limit).await;
+            info!(path, rate_limit_entries = entries, "restored state");
+        }
+        Ok(None) => {}
+        Err(e) => warn!(path, error = %e, "failed to restore state, starting with a clean slate"),
+    }
+}
+
+/// Writes the state that should survive a restart to the state file
+async fn save_state(path: &str, state: &PolicyState) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let saved = SavedState {
+        rate_limit: state.rate_limit.entries().await,
+    };
+    let entries = saved.rate_limit.len();
+    state_file::save(path, &saved)?;
+    info!(path, rate_limit_entries = entries, "saved state");
+    Ok(())
+}
+
+/// Answers a request. Events from trusted streams are accepted right away, the others have their rate limit counted
+/// in the order they arrive and are then validated concurrently, at most as many at once as `in_flight` has permits.
+/// Returns false once the writer is gone and reading more requests is pointless.
+async fn dispatch(
+    req: Request,
+    state: Arc<PolicyState>,
+    in_flight: &Arc<Semaphore>,
+    responses: &mpsc::Sender<Response>,
+) -> bool {
+    if state
+        .config
+        .sources
+        .is_trusted(req.source_type, &req.source_info)
+    {
+        debug!(event_id = %req.event.id, source_info = %req.source_info, "accepted from trusted stream");
+        let res = Response {
             id: req.event.id.to_hex(),
-            action: String::from("reject"),
-            msg: Some(String::from("blocked")),
+            action: Action::Accept,
+            msg: None,
         };
+        return responses.send(res).await.is_ok();
+    }
+
+    // Rate limits are counted here, in the order the events arrive, only the other filters run concurrently
+    let rate_limited = is_rate_limited(
+        &req.event,
+        req.source_ip(),
+        state.filters(req.source_type),
+        &state.rate_limit,
+    )
+    .await;
 
-        // Validates if the event should be persisted or not against a set of filters and modifies the response thereafter
-        match validate_event(
-            &*data_source,
+    // Wait for a free slot so a slow datasource can't pile up an unbounded number of events
+    let permit = in_flight
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("the semaphore is never closed");
+    let sender = responses.clone();
+    tokio::spawn(async move {
+        let res = process_event(&state, &req, rate_limited).await;
+        // The send only fails once the writer gave up, the read loop notices that on its own
+        let _ = sender.send(res).await;
+        drop(permit);
+    });
+
+    // Stop reading as soon as the writer is gone instead of validating events nobody will hear about
+    !responses.is_closed()
+}
+
+/// Validates a single event and builds the response for strfry
+async fn process_event(state: &PolicyState, req: &Request, rate_limited: bool) -> Response {
+    let config = &state.config;
+
+    // Build default response
+    let mut res = Response::reject(req.event.id.to_hex(), "blocked");
+
+    let verdict = if rate_limited {
+        Ok(Some(BlockedType::RateLimit))
+    } else {
+        validate_event(
+            &*state.data_source,
             &req.event,
-            &config.filters,
-            &rate_limit_engine,
+            req.source_ip(),
+            state.filters(req.source_type),
         )
         .await
-        {
-            Ok(Some(BlockedType::RateLimit)) => {
-                res.msg = Some(String::from("rate limited"));
-                print_blocked_message(&req, "rate-limited");
-            }
-            Ok(Some(BlockedType::Pubkey)) => {
-                res.msg = Some(String::from(
-                    "public key does not have permission to write to relay",
-                ));
-                print_blocked_message(&req, "not allowed to write");
-            }
-            Ok(Some(BlockedType::Kind)) => {
-                res.msg = Some(String::from("event kind blocked by relay"));
-                print_blocked_message(
-                    &req,
-                    format!("kind not accepted ({})", req.event.kind.as_u64()).as_str(),
-                );
-            }
-            Ok(Some(BlockedType::Word)) => {
-                res.msg = Some(String::from("blocked content"));
-                print_blocked_message(&req, "blocked content");
-            }
-            Ok(None) => {
-                res.action = String::from("accept");
-                res.msg = None;
-            }
-            Err(err) => {
-                eprintln!("error validating event: {}", err)
-            }
+    };
+
+    // Modify the response according to the outcome of the validation
+    match verdict {
+        Ok(Some(blocked_type)) => {
+            let (msg, reason) = match &blocked_type {
+                BlockedType::RateLimit => (String::from("rate limited"), "rate-limited"),
+                BlockedType::Pubkey => (
+                    String::from("public key does not have permission to write to relay"),
+                    "not allowed to write",
+                ),
+                BlockedType::Kind => (
+                    String::from("event kind blocked by relay"),
+                    "kind not accepted",
+                ),
+                BlockedType::Word(ContentField::Content) => {
+                    (String::from("blocked content"), "blocked content")
+                }
+                BlockedType::Word(field) => {
+                    (format!("blocked content in {}", field), "blocked content")
+                }
+                BlockedType::Ip => (
+                    String::from("address does not have permission to write to relay"),
+                    "ip not allowed",
+                ),
+            };
+            res.action = config.action_for(&blocked_type);
+            res.msg = Some(msg);
+            log_blocked(req, &blocked_type, reason, res.action);
         }
+        Ok(None) => {
+            res.action = Action::Accept;
+            res.msg = None;
+        }
+        Err(err) => {
+            METRICS.validation_errors.incr();
+            let on_error = config.on_error_for(&err.filter);
+            res.action = on_error.action;
+            res.msg = Some(on_error.msg.clone());
+            error!(
+                event_id = %req.event.id,
+                filter = ?err.filter,
+                error = %err.source,
+                action = ?on_error.action,
+                "error validating event"
+            )
+        }
+    }
 
-        // Output result of event validation, this is picked up by strfry for further processing
-        writer
-            .write_all(serde_json::to_string(&res)?.as_bytes())
-            .await
-            .unwrap();
-        writer.write_all(b"\n").await.unwrap();
+    res
+}
+
+/// Starts the task that writes responses to strfry, this is picked up by strfry for further processing. The task ends
+/// once every sender is dropped, or with an error when stdout is closed.
+fn spawn_writer(capacity: usize) -> (mpsc::Sender<Response>, WriterHandle) {
+    let (sender, mut receiver) = mpsc::channel::<Response>(capacity);
+    let handle = tokio::spawn(async move {
+        let mut writer = stdout();
+        while let Some(res) = receiver.recv().await {
+            write_response(&mut writer, &res).await?;
+        }
+        writer.flush().await?;
+        Ok(())
+    });
+    (sender, handle)
+}
+
+/// Writes a single response line to strfry. Only a closed stdout is fatal, other write errors are logged and skipped.
+async fn write_response(
+    writer: &mut Stdout,
+    res: &Response,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if res.action == Action::Accept {
+        METRICS.accepted.incr();
+    } else {
+        METRICS.rejected.incr();
+    }
+
+    let mut line = serde_json::to_vec(res)?;
+    line.push(b'\n');
+
+    if let Err(e) = writer.write_all(&line).await {
+        METRICS.write_errors.incr();
+        if e.kind() == ErrorKind::BrokenPipe {
+            error!(error = %e, "stdout closed, shutting down");
+            return Err(e.into());
+        }
+        error!(id = %res.id, error = %e, "failed to write response");
     }
 
     Ok(())
 }
 
-fn print_blocked_message(req: &Request, reason: &str) {
-    println!(
-        "[BLOCKED] public key {} from {}: {}",
-        req.event.pubkey, req.source_info, reason
+fn log_blocked(req: &Request, blocked_type: &BlockedType, reason: &str, action: Action) {
+    // The content filter reports which part of the event had the blocked content
+    let field = match blocked_type {
+        BlockedType::Word(field) => Some(field.to_string()),
+        _ => None,
+    };
+    info!(
+        event_id = %req.event.id,
+        pubkey = %req.event.pubkey,
+        kind = req.event.kind.as_u64(),
+        source_type = %req.source_type,
+        source_info = %req.source_info,
+        blocked_type = ?blocked_type,
+        field,
+        action = ?action,
+        reason,
+        "event blocked"
     );
 }
 
-// Handling the error in case the database query fails
-struct MyPGError(PGError);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chief::engine::config::FilterModeConfig;
+    use chief::engine::validation::{ValidationDataSource, ValidationFuture};
+    use nostr_sdk::{Event, EventBuilder, Keys};
+    use serde_json::json;
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
-impl From<PGError> for MyPGError {
-    fn from(error: PGError) -> Self {
-        MyPGError(error)
+    const CLIENT: (&str, &str) = ("IP4", "203.0.113.7");
+
+    /// A datasource that allows everything, but takes as many milliseconds to check the content as the content says
+    #[derive(Clone, Default)]
+    struct SlowDataSource {
+        running: Arc<AtomicUsize>,
+        max_running: Arc<AtomicUsize>,
     }
-}
 
-impl From<MyPGError> for Box<dyn Error> {
-    fn from(error: MyPGError) -> Box<dyn Error> {
-        Box::new(error.0)
+    impl ValidationDataSource for SlowDataSource {
+        fn is_pubkey_allowed(&self, _: &str, _: FilterModeConfig) -> ValidationFuture<'_> {
+            Box::pin(async { Ok(true) })
+        }
+
+        fn is_kind_allowed(&self, _: u32, _: FilterModeConfig) -> ValidationFuture<'_> {
+            Box::pin(async { Ok(true) })
+        }
+
+        fn is_content_allowed(&self, content: &str) -> ValidationFuture<'_> {
+            let delay = Duration::from_millis(content.parse().unwrap());
+            Box::pin(async move {
+                let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_running.fetch_max(running, Ordering::SeqCst);
+                tokio::time::sleep(delay).await;
+                self.running.fetch_sub(1, Ordering::SeqCst);
+                Ok(true)
+            })
+        }
+
+        fn is_ip_allowed(&self, _: IpAddr, _: FilterModeConfig) -> ValidationFuture<'_> {
+            Box::pin(async { Ok(true) })
+        }
+    }
+
+    fn state(data_source: &SlowDataSource, rate_limit: &str) -> Arc<PolicyState> {
+        let config: Config = toml::from_str(&format!(
+            r#"
+            datasource_mode = "Json"
+
+            [filters]
+            pubkey = {{ enabled = false, filter_mode = "Whitelist" }}
+            kind = {{ enabled = false, filter_mode = "Blacklist" }}
+            content = {{ enabled = true, validated_kinds = [] }}
+            rate_limit = {}
+
+            [database]
+
+            [json]
+            file_path = ""
+
+            [sources]
+            trusted_streams = ["wss://trusted.example.com"]
+            "#,
+            rate_limit
+        ))
+        .unwrap();
+        Arc::new(PolicyState::new(
+            config,
+            Box::new(data_source.clone()),
+            None,
+        ))
+    }
+
+    fn request(event: &Event, source_type: &str, source_info: &str) -> Request {
+        let line = json!({
+            "type": "new",
+            "event": event,
+            "receivedAt": 0,
+            "sourceType": source_type,
+            "sourceInfo": source_info,
+        });
+        decode_request(&line.to_string()).unwrap()
+    }
+
+    /// Dispatches the events in turn and returns the responses once all of them are answered
+    async fn dispatch_all(
+        state: &Arc<PolicyState>,
+        events: &[Event],
+        max_in_flight: usize,
+        (source_type, source_info): (&str, &str),
+    ) -> Vec<Response> {
+        let (responses, mut receiver) = mpsc::channel(events.len());
+        let in_flight = Arc::new(Semaphore::new(max_in_flight));
+        for event in events {
+            let req = request(event, source_type, source_info);
+            assert!(dispatch(req, state.clone(), &in_flight, &responses).await);
+        }
+        drop(responses);
+
+        let mut answered = Vec::new();
+        while let Some(res) = receiver.recv().await {
+            answered.push(res);
+        }
+        answered
+    }
+
+    fn events(keys: &Keys, delays: &[u64]) -> Vec<Event> {
+        delays
+            .iter()
+            .map(|delay| {
+                EventBuilder::text_note(delay.to_string(), [])
+                    .to_event(keys)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_earliest_events_of_a_burst_are_accepted() {
+        let data_source = SlowDataSource::default();
+        let state = state(
+            &data_source,
+            "{ enabled = true, max_events = 3, time_window = 60 }",
+        );
+        // The first events take the longest to validate, so they finish last
+        let events = events(&Keys::generate(), &[60, 50, 40, 30, 20, 10]);
+
+        let responses = dispatch_all(&state, &events, 8, CLIENT).await;
+
+        assert_eq!(responses.len(), events.len());
+        let mut accepted: Vec<_> = responses
+            .iter()
+            .filter(|res| res.action == Action::Accept)
+            .map(|res| res.id.clone())
+            .collect();
+        accepted.sort();
+        let mut expected: Vec<_> = events[..3].iter().map(|e| e.id.to_hex()).collect();
+        expected.sort();
+        assert_eq!(accepted, expected);
+        assert!(responses
+            .iter()
+            .filter(|res| res.action != Action::Accept)
+            .all(|res| res.msg.as_deref() == Some("rate limited")));
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_limit() {
+        let data_source = SlowDataSource::default();
+        let state = state(
+            &data_source,
+            "{ enabled = false, max_events = 3, time_window = 60 }",
+        );
+        let events = events(&Keys::generate(), &[20; 8]);
+
+        let responses = dispatch_all(&state, &events, 2, CLIENT).await;
+
+        assert_eq!(responses.len(), events.len());
+        assert!(responses.iter().all(|res| res.action == Action::Accept));
+        assert_eq!(data_source.max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_trusted_streams_skip_the_filters() {
+        let data_source = SlowDataSource::default();
+        let state = state(
+            &data_source,
+            "{ enabled = true, max_events = 1, time_window = 60 }",
+        );
+        let keys = Keys::generate();
+
+        // Neither rate limited nor validated, and they don't count towards the limit either
+        let responses = dispatch_all(
+            &state,
+            &events(&keys, &[0, 0, 0]),
+            8,
+            ("Stream", "wss://trusted.example.com/"),
+        )
+        .await;
+        assert!(responses.iter().all(|res| res.action == Action::Accept));
+        assert_eq!(data_source.max_running.load(Ordering::SeqCst), 0);
+
+        let responses = dispatch_all(
+            &state,
+            &events(&keys, &[0, 0]),
+            8,
+            ("Stream", "wss://other.example.com"),
+        )
+        .await;
+        let accepted = responses
+            .iter()
+            .filter(|res| res.action == Action::Accept)
+            .count();
+        assert_eq!(accepted, 1);
+        assert_eq!(data_source.max_running.load(Ordering::SeqCst), 1);
     }
 }
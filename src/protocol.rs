@@ -0,0 +1,185 @@
+use chief::engine::config::{Action, SourceType};
+use nostr_sdk::{Event, EventId};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::fmt::Formatter;
+use std::net::IpAddr;
+
+/// Represents a request from the relay
+#[derive(Deserialize)]
+pub struct Request {
+    #[serde(rename = "type")]
+    pub type_field: String,
+    pub event: Event,
+    #[serde(rename = "receivedAt")]
+    pub _received_at: u64,
+    #[serde(rename = "sourceType", deserialize_with = "deserialize_source_type")]
+    pub source_type: SourceType,
+    #[serde(rename = "sourceInfo")]
+    pub source_info: String,
+}
+
+impl Request {
+    /// The address of the client that submitted the event. Events strfry got some other way, e.g. through an import,
+    /// a stream or a sync with another relay, have none.
+    pub fn source_ip(&self) -> Option<IpAddr> {
+        match self.source_type {
+            SourceType::Ip4 | SourceType::Ip6 => self
+                .source_info
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Source types chief doesn't know about are kept as [`SourceType::Unknown`] instead of failing the whole request
+fn deserialize_source_type<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<SourceType, D::Error> {
+    let source_type = String::deserialize(deserializer)?;
+    Ok(serde_json::from_value(Value::String(source_type)).unwrap_or(SourceType::Unknown))
+}
+
+/// Represents the response we provide back to the relay
+#[derive(Serialize)]
+pub struct Response {
+    pub id: String,
+    pub action: Action,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub msg: Option<String>,
+}
+
+impl Response {
+    pub fn reject(id: String, msg: &str) -> Self {
+        Response {
+            id,
+            action: Action::Reject,
+            msg: Some(msg.to_owned()),
+        }
+    }
+}
+
+/// A request line that could not be turned into a [`Request`]
+#[derive(Debug)]
+pub struct DecodeError {
+    /// The event id, if it could still be recovered from the malformed request
+    pub event_id: Option<EventId>,
+    pub reason: String,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+/// Decodes a single line from strfry. On failure, tries to salvage the event id so that the relay still gets an answer.
+pub fn decode_request(line: &str) -> Result<Request, DecodeError> {
+    serde_json::from_str::<Request>(line).map_err(|e| DecodeError {
+        event_id: recover_event_id(line),
+        reason: e.to_string(),
+    })
+}
+
+fn recover_event_id(line: &str) -> Option<EventId> {
+    let value: Value = serde_json::from_str(line).ok()?;
+    let id = value.get("event")?.get("id")?.as_str()?;
+    EventId::from_hex(id).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::{EventBuilder, Keys};
+    use serde_json::json;
+
+    const EVENT_ID: &str = "9740e3805f649ab3de498cbadd6016231523c7915cb506506c34a04a5fedcf65";
+
+    #[test]
+    fn test_decode_recovers_event_id() {
+        let line = format!(
+            r#"{{"type":"new","event":{{"id":"{}","kind":"not a number"}},"receivedAt":1,"sourceType":"IP4","sourceInfo":"127.0.0.1"}}"#,
+            EVENT_ID
+        );
+
+        let err = decode_request(&line).err().unwrap();
+
+        assert_eq!(err.event_id.unwrap().to_hex(), EVENT_ID);
+    }
+
+    #[test]
+    fn test_response_action_uses_strfry_names() {
+        let res = Response {
+            id: String::from(EVENT_ID),
+            action: Action::ShadowReject,
+            msg: None,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            format!(r#"{{"id":"{}","action":"shadowReject"}}"#, EVENT_ID)
+        );
+    }
+
+    #[test]
+    fn test_decode_garbage() {
+        let err = decode_request("this is not json").err().unwrap();
+
+        assert!(err.event_id.is_none());
+    }
+
+    #[test]
+    fn test_decode_invalid_event_id() {
+        let err = decode_request(r#"{"type":"new","event":{"id":"nope"}}"#)
+            .err()
+            .unwrap();
+
+        assert!(err.event_id.is_none());
+    }
+
+    #[test]
+    fn test_source_ip() {
+        let event = EventBuilder::text_note("hello", [])
+            .to_event(&Keys::generate())
+            .unwrap();
+        let source_ip = |source_type: &str, source_info: &str| {
+            let line = json!({
+                "type": "new",
+                "event": event,
+                "receivedAt": 1,
+                "sourceType": source_type,
+                "sourceInfo": source_info,
+            });
+            decode_request(&line.to_string()).unwrap().source_ip()
+        };
+
+        assert_eq!(source_ip("IP4", "1.2.3.4"), "1.2.3.4".parse().ok());
+        assert_eq!(
+            source_ip("IP6", "[2001:db8::1]"),
+            "2001:db8::1".parse().ok()
+        );
+        assert_eq!(source_ip("Stream", "wss://relay.example.com"), None);
+        assert_eq!(source_ip("Import", ""), None);
+    }
+
+    #[test]
+    fn test_decode_unknown_source_type() {
+        let event = EventBuilder::text_note("hello", [])
+            .to_event(&Keys::generate())
+            .unwrap();
+        let line = json!({
+            "type": "new",
+            "event": event,
+            "receivedAt": 1,
+            "sourceType": "Carrier pigeon",
+            "sourceInfo": "",
+        });
+
+        let req = decode_request(&line.to_string()).unwrap();
+
+        assert_eq!(req.source_type, SourceType::Unknown);
+    }
+}
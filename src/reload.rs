@@ -0,0 +1,261 @@
+use chief::engine::config::DataSource;
+use chief::engine::state::{PolicyState, SharedState};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{error, info, warn};
+
+/// Editors and deployment tools often touch a file several times in a row, wait for things to settle before reloading
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Reloads the config and rebuilds the datasource on SIGHUP and, when `reload.watch_files` is enabled, whenever the
+/// config file or the JSON datasource file changes. A failed reload keeps the current state.
+pub fn spawn_reloader(
+    config_path: String,
+    shared: Arc<SharedState>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    let (tx, mut rx) = unbounded_channel();
+    let mut files = FileWatch::new(tx);
+    files.update(&config_path, &shared.current());
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => info!("received SIGHUP, reloading"),
+                Some(paths) = rx.recv() => {
+                    if !files.is_relevant(&paths) {
+                        continue;
+                    }
+                    tokio::time::sleep(WATCH_DEBOUNCE).await;
+                    while rx.try_recv().is_ok() {}
+                    info!("watched file changed, reloading");
+                }
+            }
+
+            reload(&config_path, &shared, &mut files).await;
+        }
+    });
+
+    Ok(())
+}
+
+/// Loads the config and swaps in the new state, returns whether it succeeded
+async fn reload(config_path: &str, shared: &SharedState, files: &mut FileWatch) -> bool {
+    match PolicyState::load(config_path, Some(&shared.current())).await {
+        Ok(state) => {
+            files.update(config_path, &state);
+            shared.replace(state);
+            info!(config = %config_path, "reloaded config and datasource");
+            true
+        }
+        Err(e) => {
+            error!(config = %config_path, error = %e, "reload failed, keeping the current config");
+            false
+        }
+    }
+}
+
+/// Watches the directories holding the files we care about. Watching the directory rather than the file itself
+/// also catches editors that save by writing a new file and renaming it over the old one.
+struct FileWatch {
+    tx: UnboundedSender<Vec<PathBuf>>,
+    watcher: Option<RecommendedWatcher>,
+    directories: HashSet<PathBuf>,
+    files: HashSet<PathBuf>,
+}
+
+impl FileWatch {
+    fn new(tx: UnboundedSender<Vec<PathBuf>>) -> Self {
+        FileWatch {
+            tx,
+            watcher: None,
+            directories: HashSet::new(),
+            files: HashSet::new(),
+        }
+    }
+
+    fn is_relevant(&self, paths: &[PathBuf]) -> bool {
+        paths.iter().any(|path| self.files.contains(path))
+    }
+
+    /// Brings the watched files in line with the given state
+    fn update(&mut self, config_path: &str, state: &PolicyState) {
+        if !state.config.reload.watch_files {
+            self.watcher = None;
+            self.directories.clear();
+            self.files.clear();
+            return;
+        }
+
+        let mut files = vec![config_path];
+        if state.config.datasource_mode == DataSource::Json {
+            files.push(state.config.json.file_path.as_str());
+        }
+        self.files = files.into_iter().filter_map(absolute).collect();
+
+        if self.watcher.is_none() {
+            let tx = self.tx.clone();
+            let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                if let Ok(event) = res {
+                    if !event.kind.is_access() {
+                        let _ = tx.send(event.paths);
+                    }
+                }
+            });
+            match watcher {
+                Ok(watcher) => self.watcher = Some(watcher),
+                Err(e) => {
+                    warn!(error = %e, "unable to watch files for changes, only SIGHUP will trigger a reload");
+                    return;
+                }
+            }
+        }
+
+        let directories: HashSet<PathBuf> = self
+            .files
+            .iter()
+            .filter_map(|file| file.parent().map(Path::to_path_buf))
+            .collect();
+        if let Some(watcher) = self.watcher.as_mut() {
+            for directory in self.directories.difference(&directories) {
+                let _ = watcher.unwatch(directory);
+            }
+            for directory in directories.difference(&self.directories) {
+                if let Err(e) = watcher.watch(directory, RecursiveMode::NonRecursive) {
+                    warn!(directory = %directory.display(), error = %e, "unable to watch directory");
+                }
+            }
+        }
+        self.directories = directories;
+    }
+}
+
+fn absolute(path: &str) -> Option<PathBuf> {
+    std::path::absolute(path).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// A directory with a JSON mode config and its datasource, removed again when dropped
+    struct ConfigDir(PathBuf);
+
+    impl ConfigDir {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("chief-reload-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let dir = ConfigDir(dir);
+            std::fs::write(
+                dir.path("data.json"),
+                r#"{"pubkeys": [], "kinds": [], "words": []}"#,
+            )
+            .unwrap();
+            dir
+        }
+
+        fn path(&self, file: &str) -> PathBuf {
+            self.0.join(file)
+        }
+
+        fn config_path(&self) -> String {
+            self.path("config.toml").to_str().unwrap().to_owned()
+        }
+
+        fn write_config(&self, max_in_flight: usize, watch_files: bool) {
+            let config = format!(
+                r#"
+                datasource_mode = "Json"
+
+                [filters]
+                pubkey = {{ enabled = false, filter_mode = "Whitelist" }}
+                kind = {{ enabled = false, filter_mode = "Blacklist" }}
+                content = {{ enabled = false, validated_kinds = [] }}
+                rate_limit = {{ enabled = false, max_events = 10, time_window = 60 }}
+
+                [database]
+
+                [json]
+                file_path = {}
+
+                [processing]
+                max_in_flight = {}
+
+                [reload]
+                watch_files = {}
+                "#,
+                toml::Value::from(self.path("data.json").to_str().unwrap()),
+                max_in_flight,
+                watch_files
+            );
+            std::fs::write(self.config_path(), config).unwrap();
+        }
+    }
+
+    impl Drop for ConfigDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_reload_keeps_state() {
+        let dir = ConfigDir::new("failed");
+        dir.write_config(4, false);
+        let config_path = dir.config_path();
+        let shared = SharedState::new(PolicyState::load(&config_path, None).await.unwrap());
+        let (tx, _rx) = unbounded_channel();
+        let mut files = FileWatch::new(tx);
+        let before = shared.current();
+
+        std::fs::write(&config_path, "datasource_mode = ").unwrap();
+        assert!(!reload(&config_path, &shared, &mut files).await);
+        assert!(Arc::ptr_eq(&before, &shared.current()));
+
+        // A config whose datasource can't be built is rejected as well
+        dir.write_config(8, false);
+        std::fs::remove_file(dir.path("data.json")).unwrap();
+        assert!(!reload(&config_path, &shared, &mut files).await);
+        assert!(Arc::ptr_eq(&before, &shared.current()));
+
+        std::fs::write(
+            dir.path("data.json"),
+            r#"{"pubkeys": [], "kinds": [], "words": []}"#,
+        )
+        .unwrap();
+        assert!(reload(&config_path, &shared, &mut files).await);
+        assert_eq!(shared.current().config.processing.max_in_flight, 8);
+    }
+
+    #[tokio::test]
+    async fn test_reload_on_file_change() {
+        let dir = ConfigDir::new("watch");
+        dir.write_config(4, true);
+        let config_path = dir.config_path();
+        let shared = Arc::new(SharedState::new(
+            PolicyState::load(&config_path, None).await.unwrap(),
+        ));
+        spawn_reloader(config_path.clone(), shared.clone()).unwrap();
+
+        // Files next to the watched ones don't trigger a reload
+        std::fs::write(dir.path("other.txt"), "").unwrap();
+        tokio::time::sleep(WATCH_DEBOUNCE * 2).await;
+        assert_eq!(shared.current().config.processing.max_in_flight, 4);
+
+        dir.write_config(8, true);
+        for _ in 0..50 {
+            if shared.current().config.processing.max_in_flight == 8 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("config change was not picked up");
+    }
+}